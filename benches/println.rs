@@ -0,0 +1,135 @@
+// Times the interpreter on examples/println.mur, `cargo bench`.
+// The example prints a constant, so the bigger cases print a register given by `--set` instead,
+// its unary arithmetic makes the number of steps grow fast with the value.
+//
+// The first table runs the program as written through the library: with registers kept inline
+// in the machine, and with every register a `BigNat` as the machine kept them before, then with
+// the counting loops fused. The second one times the whole binary, which fuses loops too.
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use mur::bignat::BigNat;
+use mur::optimize;
+use mur::parser::Parser;
+use mur::vm::{execute, execute_fused, Input, InputMode, Limits, Op, Output, OutputMode};
+
+const RUNS: usize = 5;
+
+fn run(program: &PathBuf, args: &[String]) -> Duration {
+    let start = Instant::now();
+    let status = Command::new(env!("CARGO_BIN_EXE_MUR-interpreter"))
+        .arg("run")
+        .arg(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .status()
+        .expect("cannot run the interpreter");
+    let time = start.elapsed();
+
+    assert!(status.success(), "the interpreter failed on {}", program.display());
+    time
+}
+
+fn report(name: &str, mut times: Vec<Duration>) {
+    times.sort();
+
+    println!("{:<32} min {:>10.3?}  median {:>10.3?}", name, times[0], times[RUNS / 2]);
+}
+
+fn bench(name: &str, program: &PathBuf, args: &[String]) {
+    report(name, (0..RUNS).map(|_| run(program, args)).collect());
+}
+
+fn time(f: impl Fn() -> u64) -> (Duration, u64) {
+    let start = Instant::now();
+    let steps = f();
+    (start.elapsed(), steps)
+}
+
+// The instructions with every register a `BigNat`, output is dropped, returns the number of steps
+fn run_bignat(code: &[Op], mut regs: Vec<BigNat>) -> u64 {
+    let mut ip = 0;
+    let mut steps = 0;
+
+    while ip < code.len() {
+        steps += 1;
+
+        match code[ip] {
+            Op::Zero(r) => regs[r].zero(),
+            Op::Inc(r) => regs[r].increment(),
+            Op::Mov(r1, r2) => {
+                let value = regs[r2].clone();
+                regs[r1] = value;
+            },
+            Op::Jmp(r1, r2, to) => {
+                if regs[r1] == regs[r2] {
+                    ip = to;
+                    continue;
+                }
+            },
+            Op::Out(_) => (),
+            op => panic!("{:?} isn't expected in the benchmark", op),
+        }
+
+        ip += 1;
+    }
+
+    steps
+}
+
+// the same program through the machine and through `run_bignat`, the step counts must agree
+fn bench_lib(name: &str, path: &Path, n: u64) {
+    let mut parser = Parser::open(path).expect("cannot open the benchmark program");
+    parser.set_inputs(vec![String::from("n")]);
+    let program = parser.parse().unwrap_or_else(|_| panic!("cannot parse {}", path.display()));
+
+    let size = program.code.iter().flat_map(|op| op.regs()).max().map_or(0, |r| r + 1);
+    let mut regs = vec![BigNat::new(); size];
+    regs[program.register("n").unwrap()] = BigNat::from(n);
+
+    let fused = optimize::fuse_loops(&program.code);
+
+    let input = || Input::new(Cursor::new(Vec::new()), InputMode::Byte);
+    let output = || Output::new(io::sink(), OutputMode::Char);
+    let machine = |code: &[Op]| execute(code, regs.clone(), &Limits::default(), input(), output()).steps;
+
+    let inline: Vec<_> = (0..RUNS).map(|_| time(|| machine(&program.code))).collect();
+    let bignat: Vec<_> = (0..RUNS).map(|_| time(|| run_bignat(&program.code, regs.clone()))).collect();
+    let fused: Vec<_> = (0..RUNS).map(|_| time(|| execute_fused(&fused, regs.clone(), &Limits::default(), input(), output()).steps)).collect();
+
+    assert_eq!(inline[0].1, bignat[0].1, "the machine and the BigNat loop took different steps");
+    assert_eq!(inline[0].1, fused[0].1, "fused loops took different steps");
+
+    println!("{}, {} steps", name, inline[0].1);
+    report("  inline registers", inline.iter().map(|t| t.0).collect());
+    report("  BigNat registers", bignat.iter().map(|t| t.0).collect());
+    report("  inline registers, fused loops", fused.iter().map(|t| t.0).collect());
+}
+
+fn main() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let example = root.join("examples").join("println.mur");
+
+    let text = fs::read_to_string(&example).expect("cannot read examples/println.mur");
+    let scaled = text.replace("\nmain\n", "\nprint10ln %n\n");
+    assert!(scaled != text, "examples/println.mur has no `main` call to replace");
+
+    let program = std::env::temp_dir().join(format!("mur-bench-{}.mur", std::process::id()));
+    fs::write(&program, scaled).expect("cannot write the benchmark program");
+
+    for n in [100, 500, 1234] {
+        bench_lib(&format!("println.mur, n = {}", n), &program, n);
+    }
+
+    println!();
+    bench("binary, println.mur", &example, &[]);
+
+    for n in [100, 500, 1234] {
+        bench(&format!("binary, println.mur, n = {}", n), &program, &[String::from("--set"), format!("n={}", n)]);
+    }
+
+    let _ = fs::remove_file(&program);
+}
@@ -1,61 +1,694 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Write};
+use std::ops::{Add, AddAssign, Mul, Shl, Shr};
+use std::str::FromStr;
 
 type NatBase = u64;
 
-#[derive(Clone, PartialEq, Eq)]
-struct Node {
-    pub value: NatBase,
-    next: Option<Box<Node>>
+const BASE_BITS: usize = NatBase::BITS as usize;
+
+// Natural number of any size, limbs are stored from the least significant.
+// The highest limb is never zero, so zero has no limbs and equal numbers have equal vectors.
+#[derive(Clone, PartialEq, Eq, Hash, Default)]
+pub struct BigNat {
+    limbs: Vec<NatBase>
+}
+
+impl From<u64> for BigNat {
+    fn from(value: u64) -> Self {
+        BigNat::from_limbs(vec![value])
+    }
+}
+
+impl From<u128> for BigNat {
+    fn from(value: u128) -> Self {
+        BigNat::from_limbs(vec![value as NatBase, (value >> BASE_BITS) as NatBase])
+    }
 }
 
-impl Node {
-    pub fn zero() -> Self {
-        Node {
-            value: 0,
-            next: None
+impl BigNat {
+    pub fn new() -> Self {
+        BigNat {
+            limbs: Vec::new()
         }
     }
 
-    pub fn one() -> Self {
-        Node {
-            value: 1,
-            next: None
+    fn from_limbs(limbs: Vec<NatBase>) -> Self {
+        let mut n = BigNat { limbs: limbs };
+        n.normalize();
+        n
+    }
+
+    fn normalize(&mut self) {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
         }
     }
 
+    // from the least significant
+    pub fn limbs(&self) -> &[NatBase] {
+        &self.limbs
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn zero(&mut self) {
+        self.limbs.clear();
+    }
+
     pub fn increment(&mut self) {
-        self.value += 1;
+        for limb in self.limbs.iter_mut() {
+            *limb = limb.wrapping_add(1);
 
-        if self.value == 0 {
-            match &mut self.next {
-                Some(n) => n.increment(),
-                None => self.next = Some(Box::new(Node::one())),
+            if *limb != 0 {
+                return;
             }
         }
+
+        self.limbs.push(1);
+    }
+
+    pub fn to_u64(&self) -> Option<u64> {
+        match self.limbs.as_slice() {
+            [] => Some(0),
+            [a] => Some(*a),
+            _ => None,
+        }
+    }
+
+    pub fn to_u128(&self) -> Option<u128> {
+        match self.limbs.as_slice() {
+            [] => Some(0),
+            [a] => Some(*a as u128),
+            [a, b] => Some(*a as u128 | (*b as u128) << BASE_BITS),
+            _ => None,
+        }
+    }
+
+    // number of significant bits, 0 for zero
+    pub fn bits(&self) -> usize {
+        match self.limbs.last() {
+            Some(top) => self.limbs.len() * BASE_BITS - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    pub fn checked_sub(&self, other: &BigNat) -> Option<BigNat> {
+        if *self < *other {
+            return None;
+        }
+
+        let mut res = self.clone();
+        sub_limbs(&mut res.limbs, &other.limbs);
+        res.normalize();
+
+        Some(res)
+    }
+
+    pub fn saturating_sub(&self, other: &BigNat) -> BigNat {
+        self.checked_sub(other).unwrap_or_default()
+    }
+
+    // None on division by zero
+    pub fn div_rem(&self, other: &BigNat) -> Option<(BigNat, BigNat)> {
+        if other.is_zero() {
+            return None;
+        }
+
+        if *self < *other {
+            return Some((BigNat::new(), self.clone()));
+        }
+
+        if let [d] = other.limbs.as_slice() {
+            let (q, r) = self.div_rem_small(*d);
+            return Some((q, BigNat::from(r)));
+        }
+
+        let (q, r) = div_rem_limbs(&self.limbs, &other.limbs);
+        Some((BigNat::from_limbs(q), BigNat::from_limbs(r)))
+    }
+
+    // `d` must not be zero
+    pub fn div_rem_small(&self, d: NatBase) -> (BigNat, NatBase) {
+        let mut q = self.clone();
+        let r = q.div_small(d);
+        (q, r)
+    }
+
+    // divides in place, returns the remainder
+    fn div_small(&mut self, d: NatBase) -> NatBase {
+        let mut rem: u128 = 0;
+
+        for limb in self.limbs.iter_mut().rev() {
+            let cur = rem << BASE_BITS | *limb as u128;
+            *limb = (cur / d as u128) as NatBase;
+            rem = cur % d as u128;
+        }
+
+        self.normalize();
+        rem as NatBase
+    }
+
+    // `self = self * m + a`
+    fn mul_small_add(&mut self, m: NatBase, a: NatBase) {
+        let mut carry = a as u128;
+
+        for limb in self.limbs.iter_mut() {
+            let cur = *limb as u128 * m as u128 + carry;
+            *limb = cur as NatBase;
+            carry = cur >> BASE_BITS;
+        }
+
+        if carry != 0 {
+            self.limbs.push(carry as NatBase);
+        }
+    }
+
+    // digits without a sign or a prefix, `radix` is from 2 to 36
+    pub fn from_str_radix(s: &str, radix: u32) -> Result<BigNat, ParseBigNatError> {
+        if s.is_empty() {
+            return Err(ParseBigNatError::Empty);
+        }
+
+        // as many digits as fit in a limb are accumulated before multiplying the whole number
+        let (chunk, chunk_mul) = chunk_size(radix);
+        let mut n = BigNat::new();
+
+        for digits in s.as_bytes().chunks(chunk) {
+            let mut value: NatBase = 0;
+            let mut mul: NatBase = 1;
+
+            for b in digits.iter() {
+                let Some(d) = (*b as char).to_digit(radix) else {
+                    return Err(ParseBigNatError::InvalidDigit);
+                };
+                value = value * radix as NatBase + d as NatBase;
+                mul *= radix as NatBase;
+            }
+
+            n.mul_small_add(if digits.len() == chunk { chunk_mul } else { mul }, value);
+        }
+
+        Ok(n)
     }
 }
 
-#[derive(Clone, PartialEq, Eq)]
-pub struct BigNat {
-    begin: Node
+// the largest number of digits `k` with `radix^k` fitting in a limb, and `radix^k`
+fn chunk_size(radix: u32) -> (usize, NatBase) {
+    let mut k = 1;
+    let mut mul = radix as NatBase;
+
+    while let Some(next) = mul.checked_mul(radix as NatBase) {
+        mul = next;
+        k += 1;
+    }
+
+    (k, mul)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParseBigNatError {
+    Empty,
+    InvalidDigit,
+}
+
+impl ParseBigNatError {
+    pub fn str(&self) -> &'static str {
+        match self {
+            ParseBigNatError::Empty => "empty number",
+            ParseBigNatError::InvalidDigit => "invalid digit in number",
+        }
+    }
+}
+
+// decimal, or hexadecimal with the `0x` prefix
+impl FromStr for BigNat {
+    type Err = ParseBigNatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => BigNat::from_str_radix(hex, 16),
+            None => BigNat::from_str_radix(s, 10),
+        }
+    }
+}
+
+// `a -= b`, `a` must not be less than `b`
+fn sub_limbs(a: &mut [NatBase], b: &[NatBase]) {
+    let mut borrow = false;
+
+    for (i, limb) in a.iter_mut().enumerate() {
+        let (d, o1) = limb.overflowing_sub(b.get(i).copied().unwrap_or(0));
+        let (d, o2) = d.overflowing_sub(borrow as NatBase);
+        *limb = d;
+        borrow = o1 || o2;
+
+        if !borrow && i >= b.len() {
+            break;
+        }
+    }
+}
+
+fn shl_limbs(a: &[NatBase], shift: u32) -> Vec<NatBase> {
+    if shift == 0 {
+        return a.to_vec();
+    }
+
+    let mut res = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0;
+
+    for limb in a.iter() {
+        res.push(limb << shift | carry);
+        carry = limb >> (BASE_BITS as u32 - shift);
+    }
+
+    res.push(carry);
+    res
+}
+
+// Long division (Knuth, TAOCP vol. 2, 4.3.1, algorithm D), `v` has at least two limbs and `u >= v`
+fn div_rem_limbs(u: &[NatBase], v: &[NatBase]) -> (Vec<NatBase>, Vec<NatBase>) {
+    const B: u128 = 1 << BASE_BITS;
+
+    // the divisor is normalized so its highest bit is set, it makes the quotient estimate off by at most 2
+    let shift = v[v.len() - 1].leading_zeros();
+    let vn = shl_limbs(v, shift);
+    let vn = &vn[..v.len()];
+    let mut un = shl_limbs(u, shift);
+    if un.len() == u.len() {
+        un.push(0);
+    }
+
+    let n = vn.len();
+    let m = u.len() - n;
+    let mut q = vec![0; m + 1];
+
+    for j in (0..=m).rev() {
+        let num = (un[j + n] as u128) << BASE_BITS | un[j + n - 1] as u128;
+        let mut qhat = num / vn[n - 1] as u128;
+        let mut rhat = num % vn[n - 1] as u128;
+
+        while qhat >= B || qhat * vn[n - 2] as u128 > (rhat << BASE_BITS | un[j + n - 2] as u128) {
+            qhat -= 1;
+            rhat += vn[n - 1] as u128;
+
+            if rhat >= B {
+                break;
+            }
+        }
+
+        // un[j..j + n + 1] -= qhat * vn
+        let mut borrow: i128 = 0;
+        let mut carry: u128 = 0;
+        for i in 0..n {
+            let p = qhat * vn[i] as u128 + carry;
+            carry = p >> BASE_BITS;
+
+            let t = un[i + j] as i128 - borrow - (p as NatBase) as i128;
+            un[i + j] = t as NatBase;
+            borrow = (t < 0) as i128;
+        }
+
+        let t = un[j + n] as i128 - borrow - carry as i128;
+        un[j + n] = t as NatBase;
+        q[j] = qhat as NatBase;
+
+        // the estimate was one too big, add the divisor back
+        if t < 0 {
+            q[j] -= 1;
+
+            let mut carry: u128 = 0;
+            for i in 0..n {
+                let s = un[i + j] as u128 + vn[i] as u128 + carry;
+                un[i + j] = s as NatBase;
+                carry = s >> BASE_BITS;
+            }
+            un[j + n] = un[j + n].wrapping_add(carry as NatBase);
+        }
+    }
+
+    let mut r: Vec<NatBase> = un[..n].to_vec();
+    if shift != 0 {
+        for i in 0..n {
+            r[i] = r[i] >> shift | r.get(i + 1).map_or(0, |next| next << (BASE_BITS as u32 - shift));
+        }
+    }
+
+    (q, r)
+}
+
+impl Ord for BigNat {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs.len().cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigNat {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl AddAssign<&BigNat> for BigNat {
+    fn add_assign(&mut self, other: &BigNat) {
+        if self.limbs.len() < other.limbs.len() {
+            self.limbs.resize(other.limbs.len(), 0);
+        }
+
+        let mut carry = false;
+        for i in 0..self.limbs.len() {
+            let (s, o1) = self.limbs[i].overflowing_add(other.limbs.get(i).copied().unwrap_or(0));
+            let (s, o2) = s.overflowing_add(carry as NatBase);
+            self.limbs[i] = s;
+            carry = o1 || o2;
+
+            if !carry && i >= other.limbs.len() {
+                break;
+            }
+        }
+
+        if carry {
+            self.limbs.push(1);
+        }
+    }
+}
+
+impl Add<&BigNat> for &BigNat {
+    type Output = BigNat;
+
+    fn add(self, other: &BigNat) -> BigNat {
+        let mut res = self.clone();
+        res += other;
+        res
+    }
+}
+
+impl Mul<&BigNat> for &BigNat {
+    type Output = BigNat;
+
+    fn mul(self, other: &BigNat) -> BigNat {
+        if self.is_zero() || other.is_zero() {
+            return BigNat::new();
+        }
+
+        let mut res = vec![0; self.limbs.len() + other.limbs.len()];
+
+        for (i, a) in self.limbs.iter().enumerate() {
+            let mut carry: u128 = 0;
+
+            for (j, b) in other.limbs.iter().enumerate() {
+                let cur = res[i + j] as u128 + *a as u128 * *b as u128 + carry;
+                res[i + j] = cur as NatBase;
+                carry = cur >> BASE_BITS;
+            }
+
+            res[i + other.limbs.len()] = carry as NatBase;
+        }
+
+        BigNat::from_limbs(res)
+    }
+}
+
+impl Shl<usize> for &BigNat {
+    type Output = BigNat;
+
+    fn shl(self, bits: usize) -> BigNat {
+        if self.is_zero() {
+            return BigNat::new();
+        }
+
+        let mut limbs = vec![0; bits / BASE_BITS];
+        limbs.extend(shl_limbs(&self.limbs, (bits % BASE_BITS) as u32));
+
+        BigNat::from_limbs(limbs)
+    }
+}
+
+impl Shr<usize> for &BigNat {
+    type Output = BigNat;
+
+    fn shr(self, bits: usize) -> BigNat {
+        let skip = bits / BASE_BITS;
+        if skip >= self.limbs.len() {
+            return BigNat::new();
+        }
+
+        let shift = (bits % BASE_BITS) as u32;
+        let src = &self.limbs[skip..];
+        let mut limbs = Vec::with_capacity(src.len());
+
+        for i in 0..src.len() {
+            let high = if shift == 0 { 0 } else { src.get(i + 1).map_or(0, |next| next << (BASE_BITS as u32 - shift)) };
+            limbs.push(src[i] >> shift | high);
+        }
+
+        BigNat::from_limbs(limbs)
+    }
+}
+
+// the number is divided by 10^19 while it's not zero, each remainder gives 19 digits
+const DEC_CHUNK: NatBase = 10_000_000_000_000_000_000;
+
+impl fmt::Display for BigNat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut n = self.clone();
+        let mut chunks = Vec::new();
+
+        while n.limbs.len() > 1 {
+            chunks.push(n.div_small(DEC_CHUNK));
+        }
+
+        let mut out = n.to_u64().unwrap().to_string();
+        out.reserve(chunks.len() * 19);
+        for chunk in chunks.iter().rev() {
+            write!(out, "{:019}", chunk)?;
+        }
+
+        f.pad_integral(true, "", &out)
+    }
+}
+
+impl fmt::Debug for BigNat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
 }
 
 impl BigNat {
-    pub fn new() -> Self {
-        BigNat {
-            begin: Node::zero()
+    // every limb but the highest is written with all its 16 digits
+    fn hex(&self, upper: bool) -> String {
+        let mut out = String::with_capacity(self.limbs.len() * 16);
+
+        for (i, limb) in self.limbs.iter().rev().enumerate() {
+            let _ = match (i, upper) {
+                (0, false) => write!(out, "{:x}", limb),
+                (0, true) => write!(out, "{:X}", limb),
+                (_, false) => write!(out, "{:016x}", limb),
+                (_, true) => write!(out, "{:016X}", limb),
+            };
         }
+
+        if out.is_empty() {
+            out.push('0');
+        }
+
+        out
     }
+}
 
-    pub fn increment(&mut self) {
-        self.begin.increment();
+impl fmt::LowerHex for BigNat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad_integral(true, "0x", &self.hex(false))
     }
+}
 
-    pub fn zero(&mut self) {
-        self.begin = Node::zero();
+impl fmt::UpperHex for BigNat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad_integral(true, "0x", &self.hex(true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HIGH: NatBase = 1 << (BASE_BITS - 1);
+    const MAX: NatBase = NatBase::MAX;
+
+    fn n(limbs: &[NatBase]) -> BigNat {
+        BigNat::from_limbs(limbs.to_vec())
+    }
+
+    // the quotient and the remainder must give back `u`
+    fn check_div(u: &BigNat, v: &BigNat) {
+        let (q, r) = u.div_rem(v).unwrap();
+
+        assert!(r < *v, "{:#x} / {:#x}: remainder {:#x}", u, v, r);
+        assert_eq!(&(&q * v) + &r, *u, "{:#x} / {:#x}", u, v);
+    }
+
+    // xorshift, with limbs which are often zero, one, the highest bit or all ones
+    struct Limbs(u64);
+
+    impl Limbs {
+        fn next(&mut self) -> NatBase {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+
+            match self.0 % 8 {
+                0 => 0,
+                1 => 1,
+                2 => HIGH,
+                3 => MAX,
+                4 => MAX - 1,
+                _ => self.0.rotate_left(23),
+            }
+        }
+
+        fn number(&mut self, len: usize) -> BigNat {
+            n(&(0..len).map(|_| self.next()).collect::<Vec<_>>())
+        }
+    }
+
+    #[test]
+    fn limbs_are_normalized() {
+        assert!(n(&[0, 0]).is_zero());
+        assert_eq!(n(&[5, 0, 0]).limbs(), &[5]);
+        assert_eq!(BigNat::from(0u128), BigNat::new());
+        assert_eq!(BigNat::from(u128::MAX).to_u128(), Some(u128::MAX));
+        assert_eq!(n(&[1, 2, 3]).to_u128(), None);
+        assert_eq!(n(&[MAX, 1]).bits(), 65);
+    }
+
+    #[test]
+    fn increment_carries() {
+        let mut x = n(&[MAX, MAX]);
+        x.increment();
+        assert_eq!(x.limbs(), &[0, 0, 1]);
+
+        let mut zero = BigNat::new();
+        zero.increment();
+        assert_eq!(zero.to_u64(), Some(1));
+    }
+
+    #[test]
+    fn add_sub_round_trip() {
+        let mut rng = Limbs(0x2545f4914f6cdd1d);
+
+        for _ in 0..2000 {
+            let (a, b) = (rng.number(4), rng.number(3));
+            let s = &a + &b;
+
+            assert_eq!(s.checked_sub(&b), Some(a.clone()));
+            assert_eq!(s.checked_sub(&a), Some(b.clone()));
+        }
+
+        assert_eq!(n(&[1]).checked_sub(&n(&[0, 1])), None);
+        assert!(n(&[1]).saturating_sub(&n(&[0, 1])).is_zero());
+        assert_eq!(n(&[0, 0, 1]).checked_sub(&n(&[1])), Some(n(&[MAX, MAX])));
     }
 
-    pub fn print(&self) {
-        let c = char::from_u32(self.begin.value as u32).unwrap();
-        print!("{}", c); // TODO: if greater not print
+    #[test]
+    fn mul() {
+        assert_eq!((&n(&[MAX]) * &n(&[MAX])).limbs(), &[1, MAX - 1]);
+        assert!((&n(&[MAX, MAX]) * &BigNat::new()).is_zero());
+        assert_eq!(&n(&[3, 5]) * &n(&[7]), n(&[21, 35]));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn shift_round_trip() {
+        let mut rng = Limbs(0x9e3779b97f4a7c15);
+
+        for bits in [0, 1, 63, 64, 65, 127, 128, 200] {
+            let x = rng.number(3);
+
+            assert_eq!(&(&x << bits) >> bits, x, "shift by {}", bits);
+            assert_eq!(&x << bits, &x * &(&n(&[1]) << bits));
+        }
+
+        assert!((&n(&[MAX, MAX]) >> 128).is_zero());
+        assert!((&BigNat::new() << 100).is_zero());
+    }
+
+    #[test]
+    fn div_edge_cases() {
+        assert_eq!(n(&[5]).div_rem(&BigNat::new()), None);
+        assert_eq!(BigNat::new().div_rem(&n(&[0, 1])), Some((BigNat::new(), BigNat::new())));
+        assert_eq!(n(&[7]).div_rem(&n(&[0, 1])), Some((BigNat::new(), n(&[7]))));
+        assert_eq!(n(&[0, 1]).div_rem(&n(&[0, 1])), Some((n(&[1]), BigNat::new())));
+
+        let (q, r) = n(&[10, 7, MAX]).div_rem_small(3);
+        assert!(r < 3);
+        assert_eq!(&(&q * &n(&[3])) + &n(&[r]), n(&[10, 7, MAX]));
+
+        // the top limb of the divisor has its highest bit set, normalizing doesn't shift
+        check_div(&n(&[MAX, MAX, MAX, MAX]), &n(&[5, HIGH]));
+        check_div(&n(&[0, 0, 0, HIGH]), &n(&[1, HIGH]));
+
+        // the top limb of the divisor is all ones
+        check_div(&n(&[MAX, MAX, MAX, MAX]), &n(&[MAX, MAX]));
+        check_div(&n(&[0, 0, 0, 1]), &n(&[1, MAX]));
+        assert_eq!(n(&[MAX, MAX, MAX, MAX]).div_rem(&n(&[MAX, MAX])), Some((n(&[1, 0, 1]), BigNat::new())));
+
+        // the estimate of the quotient is one too big and the divisor is added back
+        // (Hacker's Delight, the case of `divmnu` with 64 bit digits)
+        check_div(&n(&[0, 0, HIGH, HIGH - 1]), &n(&[1, 0, HIGH]));
+        check_div(&n(&[1, MAX, MAX, MAX - 1, MAX - 1]), &n(&[MAX, MAX - 1, MAX]));
+    }
+
+    #[test]
+    fn div_random() {
+        let mut rng = Limbs(0x853c49e6748fea9b);
+
+        for _ in 0..20000 {
+            let (ul, vl) = (1 + (rng.0 % 6) as usize, 2 + (rng.0 >> 8) as usize % 3);
+            let (u, v) = (rng.number(ul), rng.number(vl));
+
+            if !v.is_zero() {
+                check_div(&u, &v);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_and_format() {
+        let two_128 = "340282366920938463463374607431768211456";
+
+        assert_eq!(two_128.parse::<BigNat>(), Ok(n(&[0, 0, 1])));
+        assert_eq!(n(&[0, 0, 1]).to_string(), two_128);
+        assert_eq!(format!("{:#x}", n(&[0, 0, 1])), "0x100000000000000000000000000000000");
+        assert_eq!(format!("{:X}", n(&[MAX, 10])), "AFFFFFFFFFFFFFFFF");
+        assert_eq!("0xaFFFFFFFFFFFFFFFF".parse::<BigNat>(), Ok(n(&[MAX, 10])));
+
+        assert_eq!(BigNat::new().to_string(), "0");
+        assert_eq!(format!("{:x}", BigNat::new()), "0");
+        assert_eq!("000".parse::<BigNat>(), Ok(BigNat::new()));
+        assert_eq!(format!("{:>5}", n(&[42])), "   42");
+
+        // 10^19 is where a limb of decimal digits ends
+        assert_eq!("10000000000000000000".parse::<BigNat>(), Ok(n(&[DEC_CHUNK])));
+        assert_eq!(n(&[0, 1]).to_string(), "18446744073709551616");
+
+        assert_eq!("".parse::<BigNat>(), Err(ParseBigNatError::Empty));
+        assert_eq!("0x".parse::<BigNat>(), Err(ParseBigNatError::Empty));
+        assert_eq!("12a".parse::<BigNat>(), Err(ParseBigNatError::InvalidDigit));
+        assert_eq!("-1".parse::<BigNat>(), Err(ParseBigNatError::InvalidDigit));
+    }
+
+    #[test]
+    fn decimal_and_hex_round_trip() {
+        let mut rng = Limbs(0xda942042e4dd58b5);
+
+        for len in 0..8 {
+            let x = rng.number(len);
+
+            assert_eq!(x.to_string().parse::<BigNat>(), Ok(x.clone()));
+            assert_eq!(format!("{:#x}", x).parse::<BigNat>(), Ok(x.clone()));
+            assert_eq!(BigNat::from_str_radix(&format!("{:X}", x), 16), Ok(x.clone()));
+        }
+    }
+}
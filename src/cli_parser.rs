@@ -1,20 +1,148 @@
 use std::env::Args;
+use std::str::FromStr;
+use std::time::Duration;
 
+use mur::parser::DEFAULT_MAX_ERRORS;
+use mur::vm::{InputMode, Limits, OutputMode};
+
+use crate::trace::TraceOptions;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    Check,
+    Debug,
+    Dap,
+}
 
 pub struct CmdArgs {
+    command: Command,
     only_expand: bool,
+    optimize: bool, // `-O1`
+    max_errors: usize,
+    limits: Limits,
+    input_mode: InputMode,
+    out_mode: Option<OutputMode>, // overrides `pragma out`
+    presets: Vec<(String, String)>, // register name and value from `--set`
+    input_file: Option<String>,
+    dump_regs: bool,
+    dump_json: Option<String>, // file for the final registers as JSON, `-` is stdout
+    trace: Option<TraceOptions>,
+    profile: bool,
+    profile_folded: Option<String>, // file for folded stacks, `-` is stdout
+    coverage: bool,
+    coverage_lcov: Option<String>,
     file_path: String
 }
 
+fn option_value<T: FromStr>(args: &mut impl Iterator<Item = String>, name: &str) -> Result<T, String> {
+    let Some(value) = args.next() else {
+        return Err(format!("'{}' expects a value", name));
+    };
+
+    match value.parse() {
+        Ok(v) => Ok(v),
+        Err(_) => Err(format!("Bad value for '{}': {}", name, value)),
+    }
+}
+
 impl CmdArgs {
     pub fn parse(args: Args) -> Result<Self, String> {
+        let mut args = args.skip(1).peekable();
+
+        let mut command = Command::Run;
         let mut only_expand = false;
+        let mut optimize = false;
+        let mut max_errors = DEFAULT_MAX_ERRORS;
+        let mut limits = Limits::default();
+        let mut input_mode = InputMode::default();
+        let mut out_mode = None;
+        let mut presets = Vec::new();
+        let mut input_file = None;
+        let mut dump_regs = false;
+        let mut dump_json = None;
+        let mut trace: Option<TraceOptions> = None;
+        let mut profile = false;
+        let mut profile_folded = None;
+        let mut coverage = false;
+        let mut coverage_lcov = None;
         let mut file_path = String::from("");
 
-        for i in args.into_iter().skip(1) {
-            if i.starts_with('-') {
-                let i = &i[1..];
+        match args.peek().map(|s| s.as_str()) {
+            Some("run") => { args.next(); },
+            Some("check") => {
+                command = Command::Check;
+                args.next();
+            },
+            Some("dap") => {
+                command = Command::Dap;
+                args.next();
+            },
+            Some("debug") => {
+                command = Command::Debug;
+                args.next();
+            },
+            _ => (),
+        }
+
+        while let Some(i) = args.next() {
+            if let Some(i) = i.strip_prefix("--") {
+                match i {
+                    "max-errors" => {
+                        max_errors = option_value(&mut args, "--max-errors")?;
+
+                        // no errors shown would make a broken program look correct
+                        if max_errors == 0 {
+                            return Err(String::from("'--max-errors' must be at least 1"));
+                        }
+                    },
+                    "max-steps" => limits.max_steps = Some(option_value(&mut args, "--max-steps")?),
+                    "timeout" => {
+                        let secs: f64 = option_value(&mut args, "--timeout")?;
+
+                        match Duration::try_from_secs_f64(secs) {
+                            Ok(t) => limits.timeout = Some(t),
+                            Err(_) => return Err(format!("Bad value for '--timeout': {}", secs)),
+                        }
+                    },
+                    "inp-mode" => input_mode = match args.next().as_deref() {
+                        Some("byte") => InputMode::Byte,
+                        Some("number") => InputMode::Number,
+                        _ => return Err(String::from("'--inp-mode' expects 'byte' or 'number'")),
+                    },
+                    "out-mode" => match args.next().as_deref().and_then(OutputMode::from_name) {
+                        Some(mode) => out_mode = Some(mode),
+                        None => return Err(String::from("'--out-mode' expects 'char', 'byte', 'dec' or 'hex'")),
+                    },
+                    "set" => {
+                        let assignment: String = option_value(&mut args, "--set")?;
+
+                        let Some((name, value)) = assignment.split_once('=') else {
+                            return Err(format!("'--set' expects 'name=value', got: {}", assignment));
+                        };
 
+                        presets.push((String::from(name), String::from(value)));
+                    },
+                    "input" => input_file = Some(option_value(&mut args, "--input")?),
+                    "dump-regs" => dump_regs = true,
+                    "trace" => { trace.get_or_insert_with(TraceOptions::default); },
+                    "trace-range" => {
+                        let range: String = option_value(&mut args, "--trace-range")?;
+                        trace.get_or_insert_with(TraceOptions::default).parse_range(&range)?;
+                    },
+                    "trace-macro" => trace.get_or_insert_with(TraceOptions::default).macro_name = Some(option_value(&mut args, "--trace-macro")?),
+                    "profile" => profile = true,
+                    "profile-folded" => profile_folded = Some(option_value(&mut args, "--profile-folded")?),
+                    "coverage" => coverage = true,
+                    "coverage-lcov" => coverage_lcov = Some(option_value(&mut args, "--coverage-lcov")?),
+                    "dump-regs-json" => dump_json = Some(option_value(&mut args, "--dump-regs-json")?),
+                    _ => return Err(format!("Unknown option '--{}'", i)),
+                }
+
+                continue;
+            }
+
+            if let Some(i) = i.strip_prefix('-') {
                 if i == "m" {
                     if !only_expand {
                         only_expand = true;
@@ -23,6 +151,13 @@ impl CmdArgs {
                         return Err(String::from("'-m' was already used"));
                     }
                 }
+                else if let Some(level) = i.strip_prefix('O') {
+                    optimize = match level {
+                        "0" => false,
+                        "1" => true,
+                        _ => return Err(format!("Unknown optimization level '-O{}', expected '-O0' or '-O1'", level)),
+                    };
+                }
 
                 continue;
             }
@@ -36,16 +171,96 @@ impl CmdArgs {
         }
 
         Ok(CmdArgs {
+            command: command,
             only_expand: only_expand,
+            optimize: optimize,
+            max_errors: max_errors,
+            limits: limits,
+            input_mode: input_mode,
+            out_mode: out_mode,
+            presets: presets,
+            input_file: input_file,
+            dump_regs: dump_regs,
+            dump_json: dump_json,
+            trace: trace,
+            profile: profile,
+            profile_folded: profile_folded,
+            coverage: coverage,
+            coverage_lcov: coverage_lcov,
             file_path: file_path
         })
     }
 
+    pub fn command(&self) -> Command {
+        self.command
+    }
+
     pub fn only_expand(&self) -> bool {
         self.only_expand
     }
 
+    pub fn optimize(&self) -> bool {
+        self.optimize
+    }
+
+    pub fn max_errors(&self) -> usize {
+        self.max_errors
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn input_mode(&self) -> InputMode {
+        self.input_mode
+    }
+
+    pub fn out_mode(&self) -> Option<OutputMode> {
+        self.out_mode
+    }
+
+    pub fn presets(&self) -> &[(String, String)] {
+        &self.presets
+    }
+
+    pub fn input_file(&self) -> Option<&String> {
+        self.input_file.as_ref()
+    }
+
+    pub fn dump_regs(&self) -> bool {
+        self.dump_regs
+    }
+
+    pub fn dump_json(&self) -> Option<&String> {
+        self.dump_json.as_ref()
+    }
+
+    pub fn trace(&self) -> Option<&TraceOptions> {
+        self.trace.as_ref()
+    }
+
+    pub fn profile(&self) -> bool {
+        self.profile
+    }
+
+    pub fn profile_folded(&self) -> Option<&String> {
+        self.profile_folded.as_ref()
+    }
+
+    pub fn coverage(&self) -> bool {
+        self.coverage
+    }
+
+    pub fn coverage_lcov(&self) -> Option<&String> {
+        self.coverage_lcov.as_ref()
+    }
+
+    // instruction counts are needed for any of the profiles or coverage
+    pub fn counting(&self) -> bool {
+        self.profile || self.profile_folded.is_some() || self.coverage || self.coverage_lcov.is_some()
+    }
+
     pub fn filepath(&self) -> &String {
         &self.file_path
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,96 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::lexer::{FileId, LexPosType};
+use crate::parser::Program;
+use crate::profile::Profile;
+use crate::source::Sources;
+
+// Line coverage from the instruction counts of a profile. A line is covered if any instruction
+// from it was executed, lines inside macro bodies sum all their expansions.
+// A line with only a macro call counts how many times its expansion was entered.
+pub struct Coverage {
+    files: BTreeMap<FileId, BTreeMap<LexPosType, u64>> // only lines which produced code
+}
+
+impl Coverage {
+    pub fn new(profile: &Profile, program: &Program) -> Self {
+        let mut files: BTreeMap<FileId, BTreeMap<LexPosType, u64>> = BTreeMap::new();
+
+        for ((file, line), count) in profile.lines(program) {
+            files.entry(file).or_default().insert(line, count);
+        }
+
+        // every entry into an expansion from outside of it counts for the line of its call,
+        // jumps inside the expansion, as in a macro starting with a loop, don't
+        let frames = program.source_map.frames();
+        let mut calls: HashMap<(FileId, LexPosType), u64> = HashMap::new();
+
+        for (id, count) in profile.entries().iter().enumerate() {
+            let span = frames.get(id).span();
+            *calls.entry((span.file(), span.line())).or_insert(0) += count;
+        }
+
+        for ((file, line), count) in calls {
+            files.entry(file).or_default().entry(line).or_insert(count);
+        }
+
+        Coverage {
+            files: files
+        }
+    }
+
+    fn summary(lines: &BTreeMap<LexPosType, u64>) -> (usize, usize) {
+        (lines.values().filter(|c| **c > 0).count(), lines.len())
+    }
+
+    // every file with its lines prefixed by the count, `#####` marks lines which never ran, as gcov does
+    pub fn render(&self, sources: &Sources) -> String {
+        let mut out = String::new();
+
+        for (file, lines) in self.files.iter() {
+            out += &format!("{}:\n", sources.name(*file));
+
+            let mut line = 1;
+            while let Some(text) = sources.line(*file, line) {
+                let count = match lines.get(&line) {
+                    Some(0) => String::from("#####"),
+                    Some(n) => n.to_string(),
+                    None => String::from("-"),
+                };
+
+                out += &format!("{:>12}: {:>5}: {}\n", count, line, text);
+                line += 1;
+            }
+
+            out += "\n";
+        }
+
+        for (file, lines) in self.files.iter() {
+            let (hit, all) = Coverage::summary(lines);
+            out += &format!("{}: {}/{} lines covered ({:.1}%)\n", sources.name(*file), hit, all, hit as f64 * 100.0 / all.max(1) as f64);
+        }
+
+        out
+    }
+
+    pub fn lcov(&self, sources: &Sources) -> String {
+        let mut out = String::from("TN:\n");
+
+        for (file, lines) in self.files.iter() {
+            let path = match sources.path(*file) {
+                Some(p) => p.to_string_lossy().to_string(),
+                None => String::from(sources.name(*file)),
+            };
+
+            out += &format!("SF:{}\n", path);
+            for (line, count) in lines.iter() {
+                out += &format!("DA:{},{}\n", line, count);
+            }
+
+            let (hit, all) = Coverage::summary(lines);
+            out += &format!("LH:{}\nLF:{}\nend_of_record\n", hit, all);
+        }
+
+        out
+    }
+}
@@ -0,0 +1,513 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, Cursor, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use mur::bignat::BigNat;
+use mur::debug::{BreakpointId, Debugger, Resume, Stop};
+use mur::lexer::FileId;
+use mur::parser::{Parser, Program};
+use mur::source::Sources;
+use mur::vm::{CellType, Halt, Input, InputMode, Machine, Output, OutputMode};
+use serde_json::{json, Value};
+
+use crate::presets::Presets;
+
+const THREAD_ID: i64 = 1;
+const REGISTERS_REF: i64 = 1;
+const LOCALS_REF: i64 = 2; // locals of the stack frame `i` are at `LOCALS_REF + i`
+
+// instructions run between checks for new requests, so `pause` and `disconnect` work while the program runs
+const SLICE_STEPS: u64 = 100_000;
+
+// Debug Adapter Protocol server on stdin and stdout, `mur dap`.
+// Messages are JSON with a `Content-Length` header, see https://microsoft.github.io/debug-adapter-protocol/
+pub fn serve() {
+    // requests are read by their own thread, so they reach the session while the program runs
+    let (sender, requests) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = std::io::stdin().lock();
+
+        while let Some(req) = read_message(&mut reader) {
+            if sender.send(req).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut conn = Connection {
+        requests: requests,
+        seq: 1
+    };
+
+    // breakpoints may come before the program is launched
+    let mut pending: HashMap<String, Vec<i64>> = HashMap::new();
+
+    let args = loop {
+        let Some(req) = conn.read() else {
+            return;
+        };
+
+        match command(&req) {
+            "initialize" => conn.respond(&req, json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsTerminateRequest": true,
+                "supportsSetVariable": true
+            })),
+            "setBreakpoints" => {
+                let path = req["arguments"]["source"]["path"].as_str().unwrap_or("").to_string();
+                let lines = breakpoint_lines(&req);
+                let unverified: Vec<Value> = lines.iter().map(|l| json!({ "verified": false, "line": l })).collect();
+
+                pending.insert(path, lines);
+                conn.respond(&req, json!({ "breakpoints": unverified }));
+            },
+            "launch" => break req,
+            "disconnect" => {
+                conn.respond(&req, Value::Null);
+                return;
+            },
+            _ => conn.fail(&req, "The program isn't launched"),
+        }
+    };
+
+    let launch = &args["arguments"];
+    let Some(path) = launch["program"].as_str() else {
+        conn.fail(&args, "'program' is required to launch");
+        return;
+    };
+
+    let mut parser = match Parser::open(Path::new(path)) {
+        Ok(p) => p,
+        Err(diag) => {
+            conn.fail(&args, &diag.render(&Sources::new(), false));
+            return;
+        }
+    };
+
+    let mut presets = Presets::new();
+    if let Some(Value::Object(regs)) = launch.get("registers") {
+        for (name, value) in regs.iter() {
+            match value {
+                Value::String(s) => presets.add(name, s),
+                v => presets.add(name, &v.to_string()),
+            }
+        }
+    }
+    parser.set_inputs(presets.names());
+
+    let program = match parser.parse() {
+        Ok(p) => p,
+        Err(errors) => {
+            let text: Vec<String> = errors.iter().map(|e| e.render(parser.sources(), false)).collect();
+            conn.fail(&args, &text.join("\n\n"));
+            return;
+        }
+    };
+
+    let regs = match presets.registers(&program) {
+        Ok(regs) => regs,
+        Err(msg) => {
+            conn.fail(&args, &msg);
+            return;
+        }
+    };
+
+    let input_mode = match launch["inpMode"].as_str() {
+        Some("number") => InputMode::Number,
+        _ => InputMode::Byte,
+    };
+    let out_mode = launch["outMode"].as_str()
+        .and_then(OutputMode::from_name)
+        .or(program.pragmas.out_mode)
+        .unwrap_or_default();
+
+    // stdin and stdout belong to the protocol, `inp` reads the `input` string and `out` is sent as output events
+    let input = Input::new(Cursor::new(launch["input"].as_str().unwrap_or("").as_bytes().to_vec()), input_mode);
+    let captured = Captured::default();
+    let machine = Machine::new(&program.code, regs, input, Output::new(captured.clone(), out_mode));
+
+    let mut session = Session {
+        conn: conn,
+        debugger: Debugger::new(&program, machine),
+        sources: parser.sources(),
+        captured: captured,
+        breakpoints: HashMap::new(),
+        stop_on_entry: launch["stopOnEntry"].as_bool().unwrap_or(false),
+        running: false
+    };
+
+    session.conn.respond(&args, Value::Null);
+
+    for (path, lines) in pending.iter() {
+        session.set_breakpoints(path, lines);
+    }
+
+    session.conn.event("initialized", Value::Null);
+    session.run();
+}
+
+fn command(req: &Value) -> &str {
+    req["command"].as_str().unwrap_or("")
+}
+
+fn breakpoint_lines(req: &Value) -> Vec<i64> {
+    req["arguments"]["breakpoints"].as_array()
+        .map(|bs| bs.iter().filter_map(|b| b["line"].as_i64()).collect())
+        .unwrap_or_default()
+}
+
+// None at the end of input or on a broken message
+fn read_message(reader: &mut impl BufRead) -> Option<Value> {
+    let mut length = None;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut body = vec![0; length?];
+    reader.read_exact(&mut body).ok()?;
+
+    serde_json::from_slice(&body).ok()
+}
+
+struct Connection {
+    requests: Receiver<Value>,
+    seq: i64
+}
+
+impl Connection {
+    // waits for the next request, None when there are no more
+    fn read(&mut self) -> Option<Value> {
+        self.requests.recv().ok()
+    }
+
+    fn try_read(&mut self) -> Result<Value, TryRecvError> {
+        self.requests.try_recv()
+    }
+
+    fn send(&mut self, mut message: Value) {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+
+        let text = message.to_string();
+        let mut stdout = std::io::stdout().lock();
+        let _ = write!(stdout, "Content-Length: {}\r\n\r\n{}", text.len(), text);
+        let _ = stdout.flush();
+    }
+
+    fn respond(&mut self, req: &Value, body: Value) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "success": true,
+            "command": req["command"],
+            "body": body
+        }));
+    }
+
+    fn fail(&mut self, req: &Value, message: &str) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "success": false,
+            "command": req["command"],
+            "message": message
+        }));
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        self.send(json!({
+            "type": "event",
+            "event": event,
+            "body": body
+        }));
+    }
+}
+
+// Output of the program, sent to the client while it runs and when it stops
+#[derive(Clone, Default)]
+struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Session<'a> {
+    conn: Connection,
+    debugger: Debugger<'a>,
+    sources: &'a Sources,
+    captured: Captured,
+    breakpoints: HashMap<FileId, Vec<BreakpointId>>,
+    stop_on_entry: bool,
+    running: bool // resumed, executed in slices of `SLICE_STEPS` between requests
+}
+
+impl Session<'_> {
+    fn program(&self) -> &Program {
+        self.debugger.program()
+    }
+
+    fn run(&mut self) {
+        loop {
+            let req = if self.running {
+                if let Some(stop) = self.debugger.run_for(SLICE_STEPS) {
+                    self.running = false;
+                    self.stopped(stop);
+                    continue;
+                }
+
+                self.send_output();
+
+                match self.conn.try_read() {
+                    Ok(req) => req,
+                    Err(TryRecvError::Empty) => continue,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+            else {
+                match self.conn.read() {
+                    Some(req) => req,
+                    None => return,
+                }
+            };
+
+            match command(&req) {
+                "setBreakpoints" => {
+                    let path = req["arguments"]["source"]["path"].as_str().unwrap_or("").to_string();
+                    let verified = self.set_breakpoints(&path, &breakpoint_lines(&req));
+                    self.conn.respond(&req, json!({ "breakpoints": verified }));
+                },
+                "configurationDone" => {
+                    self.conn.respond(&req, Value::Null);
+
+                    if self.stop_on_entry {
+                        self.conn.event("stopped", json!({ "reason": "entry", "threadId": THREAD_ID }));
+                    }
+                    else {
+                        self.debugger.start(Resume::Cont);
+                        self.running = true;
+                    }
+                },
+                "threads" => self.conn.respond(&req, json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+                "stackTrace" => {
+                    let frames = self.stack_frames();
+                    self.conn.respond(&req, json!({ "stackFrames": frames, "totalFrames": frames.len() }));
+                },
+                "scopes" => {
+                    let scopes = self.scopes(req["arguments"]["frameId"].as_i64().unwrap_or(0));
+                    self.conn.respond(&req, json!({ "scopes": scopes }));
+                },
+                "variables" => {
+                    let vars = self.variables(req["arguments"]["variablesReference"].as_i64().unwrap_or(REGISTERS_REF));
+                    self.conn.respond(&req, json!({ "variables": vars }));
+                },
+                "setVariable" => self.set_variable(&req),
+                "continue" => self.resume(&req, Resume::Cont),
+                "next" => self.resume(&req, Resume::Next),
+                "stepIn" => self.resume(&req, Resume::Step),
+                "stepOut" => self.resume(&req, Resume::Finish),
+                "pause" => {
+                    self.conn.respond(&req, Value::Null);
+
+                    if self.running {
+                        self.running = false;
+                        self.send_output();
+                        self.conn.event("stopped", json!({ "reason": "pause", "threadId": THREAD_ID }));
+                    }
+                },
+                "disconnect" | "terminate" => {
+                    self.conn.respond(&req, Value::Null);
+                    return;
+                },
+                _ => self.conn.fail(&req, &format!("Request '{}' isn't supported", command(&req))),
+            }
+        }
+    }
+
+    // replaces all breakpoints of the file, returns them for the response
+    fn set_breakpoints(&mut self, path: &str, lines: &[i64]) -> Vec<Value> {
+        let file = fs::canonicalize(path).ok().and_then(|p| self.sources.find(&p));
+
+        let Some(file) = file else {
+            return lines.iter().map(|l| json!({ "verified": false, "line": l, "message": "File isn't a part of the program" })).collect();
+        };
+
+        for id in self.breakpoints.remove(&file).unwrap_or_default() {
+            self.debugger.delete(id);
+        }
+
+        let mut ids = Vec::new();
+        let mut verified = Vec::new();
+
+        for line in lines.iter() {
+            match u32::try_from(*line).ok().and_then(|l| self.debugger.break_at_line(file, l)) {
+                Some(id) => {
+                    ids.push(id);
+                    verified.push(json!({ "id": id, "verified": true, "line": line }));
+                },
+                None => verified.push(json!({ "verified": false, "line": line, "message": "No code on this line" })),
+            }
+        }
+
+        self.breakpoints.insert(file, ids);
+        verified
+    }
+
+    // the run goes on in `run` between requests
+    fn resume(&mut self, req: &Value, how: Resume) {
+        self.conn.respond(req, Value::Null);
+
+        self.debugger.start(how);
+        self.running = true;
+    }
+
+    fn send_output(&mut self) {
+        let _ = self.debugger.machine_mut().flush();
+        let output = std::mem::take(&mut *self.captured.0.borrow_mut());
+
+        if !output.is_empty() {
+            self.conn.event("output", json!({ "category": "stdout", "output": String::from_utf8_lossy(&output) }));
+        }
+    }
+
+    fn stopped(&mut self, stop: Stop) {
+        self.send_output();
+
+        match stop {
+            Stop::Step => self.conn.event("stopped", json!({ "reason": "step", "threadId": THREAD_ID })),
+            Stop::Breakpoint(id) => self.conn.event("stopped", json!({ "reason": "breakpoint", "threadId": THREAD_ID, "hitBreakpointIds": [id] })),
+            Stop::Halt(halt) => {
+                if let Halt::Error(_) = halt {
+                    let message = format!("Execution stopped ({}) at {}\n", halt.str(), self.program().source_map.pos_str(self.debugger.machine().ip(), self.sources));
+                    self.conn.event("output", json!({ "category": "stderr", "output": message }));
+                }
+
+                let code = if halt == Halt::End { 0 } else { 3 };
+                self.conn.event("exited", json!({ "exitCode": code }));
+                self.conn.event("terminated", Value::Null);
+            },
+        }
+    }
+
+    fn source(&self, file: FileId) -> Value {
+        let name = self.sources.name(file);
+        let path = self.sources.path(file).map(|p| p.to_string_lossy().to_string()).unwrap_or(String::from(name));
+
+        json!({ "name": name, "path": path })
+    }
+
+    // The innermost frame is the current instruction, the others are macro calls which expanded it.
+    // A frame is named after the macro it's in.
+    fn stack_frames(&self) -> Vec<Value> {
+        let ip = self.debugger.machine().ip();
+        let Some(pos) = self.program().source_map.pos(ip) else {
+            return Vec::new();
+        };
+
+        let stack = self.program().source_map.stack(ip);
+        let mut frames = Vec::new();
+        let mut positions = vec![pos];
+        positions.extend(stack.iter().map(|call| call.span.pos()));
+
+        for (i, pos) in positions.iter().enumerate() {
+            let name = stack.get(i).map(|call| call.name.as_str()).unwrap_or("<main>");
+
+            frames.push(json!({
+                "id": i,
+                "name": name,
+                "source": self.source(pos.file()),
+                "line": pos.line(),
+                "column": pos.sym()
+            }));
+        }
+
+        frames
+    }
+
+    // A frame of a macro has the locals of its expansion, all frames have the global registers
+    fn scopes(&self, frame: i64) -> Vec<Value> {
+        let mut scopes = Vec::new();
+
+        if !self.registers(LOCALS_REF + frame).is_empty() {
+            scopes.push(json!({ "name": "Locals", "variablesReference": LOCALS_REF + frame, "expensive": false }));
+        }
+
+        scopes.push(json!({ "name": "Registers", "variablesReference": REGISTERS_REF, "expensive": false }));
+        scopes
+    }
+
+    // registers of a scope with their names as shown
+    fn registers(&self, reference: i64) -> Vec<(String, CellType)> {
+        if reference == REGISTERS_REF {
+            let mut names: Vec<_> = self.program().registers.iter().collect();
+            names.sort_by_key(|(name, r)| (**r, name.as_str()));
+
+            return names.iter().map(|(name, r)| (format!("%{}", name), **r)).collect();
+        }
+
+        let locals = self.program().source_map.locals(self.debugger.machine().ip());
+
+        usize::try_from(reference - LOCALS_REF).ok()
+            .and_then(|frame| locals.into_iter().nth(frame))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, r)| (format!("%.{}", name), r))
+            .collect()
+    }
+
+    fn set_variable(&mut self, req: &Value) {
+        let name = req["arguments"]["name"].as_str().unwrap_or("");
+        let value = req["arguments"]["value"].as_str().unwrap_or("").trim();
+        let reference = req["arguments"]["variablesReference"].as_i64().unwrap_or(REGISTERS_REF);
+
+        let found = self.registers(reference).into_iter().find(|(shown, _)| shown[1..] == *name.strip_prefix('%').unwrap_or(name));
+
+        let Some((_, r)) = found else {
+            self.conn.fail(req, &format!("No register '{}'", name));
+            return;
+        };
+
+        match value.parse::<BigNat>() {
+            Ok(v) => {
+                self.debugger.machine_mut().set_reg(r, v);
+                let shown = self.debugger.machine().reg(r).to_string();
+                self.conn.respond(req, json!({ "value": shown }));
+            },
+            Err(err) => self.conn.fail(req, &format!("Bad value '{}': {}", value, err.str())),
+        }
+    }
+
+    fn variables(&self, reference: i64) -> Vec<Value> {
+        self.registers(reference).iter()
+            .map(|(name, r)| json!({
+                "name": name,
+                "value": self.debugger.machine().reg(*r).to_string(),
+                "variablesReference": 0
+            }))
+            .collect()
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashSet;
+
+use crate::lexer::{FileId, LexPosType};
+use crate::parser::Program;
+use crate::vm::{Halt, Machine, PosType};
+
+pub type BreakpointId = usize;
+
+struct Breakpoint {
+    id: BreakpointId,
+    ips: HashSet<PosType>
+}
+
+// Why the debugger gave control back
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stop {
+    Step,
+    Breakpoint(BreakpointId),
+    Halt(Halt),
+}
+
+// How to resume the program, see `Debugger::start`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Resume {
+    Step,
+    Next,
+    Finish,
+    Cont,
+}
+
+// Where a resumed run stops besides breakpoints, kept between the slices of `run_for`
+#[derive(Clone, Copy)]
+enum Until {
+    Step,
+    DepthAtMost(usize),
+    DepthBelow(usize),
+    Never,
+}
+
+// Runs a program under control: breakpoints, stepping into and over macro expansions
+pub struct Debugger<'a> {
+    program: &'a Program,
+    machine: Machine<'a>,
+    breakpoints: Vec<Breakpoint>,
+    next_id: BreakpointId,
+    until: Until
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(program: &'a Program, machine: Machine<'a>) -> Self {
+        Debugger {
+            program: program,
+            machine: machine,
+            breakpoints: Vec::new(),
+            next_id: 1,
+            until: Until::Never
+        }
+    }
+
+    pub fn machine(&self) -> &Machine<'a> {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine<'a> {
+        &mut self.machine
+    }
+
+    pub fn program(&self) -> &'a Program {
+        self.program
+    }
+
+    // number of macro expansions the instruction is nested in
+    pub fn depth(&self, ip: PosType) -> usize {
+        self.program.source_map.stack(ip).len()
+    }
+
+    // An instruction belongs to its own line and to the lines of all macro calls it was expanded from,
+    // so a breakpoint on a line with a macro call stops in its expansion
+    fn on_line(&self, ip: PosType, file: FileId, line: LexPosType) -> bool {
+        let Some(pos) = self.program.source_map.pos(ip) else {
+            return false;
+        };
+
+        (pos.file() == file && pos.line() == line)
+            || self.program.source_map.stack(ip).iter().any(|call| call.span.file() == file && call.span.line() == line)
+    }
+
+    fn add(&mut self, ips: HashSet<PosType>) -> Option<BreakpointId> {
+        if ips.is_empty() {
+            return None;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint { id: id, ips: ips });
+
+        Some(id)
+    }
+
+    // None if no instruction comes from the line
+    pub fn break_at_line(&mut self, file: FileId, line: LexPosType) -> Option<BreakpointId> {
+        let ips = (0..self.program.code.len()).filter(|ip| self.on_line(*ip, file, line)).collect();
+        self.add(ips)
+    }
+
+    // local labels are found in every expansion of their macro
+    pub fn break_at_label(&mut self, name: &str) -> Option<BreakpointId> {
+        let ips = (0..self.program.code.len())
+            .filter(|ip| match self.program.source_map.label(*ip) {
+                Some(label) => label == name || label.strip_prefix(name).and_then(|s| s.strip_prefix('_')).is_some_and(|s| s.parse::<u64>().is_ok()),
+                None => false,
+            })
+            .collect();
+
+        self.add(ips)
+    }
+
+    pub fn delete(&mut self, id: BreakpointId) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|b| b.id != id);
+        self.breakpoints.len() != len
+    }
+
+    pub fn delete_all(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    // Sets how the next `run_for` goes on, depths are taken at the current instruction
+    pub fn start(&mut self, how: Resume) {
+        let depth = self.depth(self.machine.ip());
+
+        self.until = match how {
+            Resume::Step => Until::Step,
+            Resume::Next => Until::DepthAtMost(depth),
+            Resume::Finish => Until::DepthBelow(depth),
+            Resume::Cont => Until::Never,
+        };
+    }
+
+    // Executes at most `steps` instructions of the run set by `start`, None if it hasn't stopped by then.
+    // A breakpoint stops only when execution comes from outside of it, not on every instruction of its line.
+    pub fn run_for(&mut self, steps: u64) -> Option<Stop> {
+        for _ in 0..steps {
+            let from = self.machine.ip();
+
+            if let Some(halt) = self.machine.step() {
+                return Some(Stop::Halt(halt));
+            }
+
+            let ip = self.machine.ip();
+
+            if let Some(b) = self.breakpoints.iter().find(|b| b.ips.contains(&ip) && !b.ips.contains(&from)) {
+                return Some(Stop::Breakpoint(b.id));
+            }
+
+            // the end is reported at once, not on the next command
+            if let Some(halt) = self.machine.finished() {
+                return Some(Stop::Halt(halt));
+            }
+
+            let done = match self.until {
+                Until::Step => true,
+                Until::DepthAtMost(depth) => self.depth(ip) <= depth,
+                Until::DepthBelow(depth) => self.depth(ip) < depth,
+                Until::Never => false,
+            };
+
+            if done {
+                return Some(Stop::Step);
+            }
+        }
+
+        None
+    }
+
+    fn run(&mut self, how: Resume) -> Stop {
+        self.start(how);
+
+        loop {
+            if let Some(stop) = self.run_for(u64::MAX) {
+                return stop;
+            }
+        }
+    }
+
+    // one instruction, enters macro expansions
+    pub fn step(&mut self) -> Stop {
+        self.run(Resume::Step)
+    }
+
+    // to the next instruction which isn't nested deeper than the current one, macro calls are passed as a whole
+    pub fn next(&mut self) -> Stop {
+        self.run(Resume::Next)
+    }
+
+    // out of the current macro expansion
+    pub fn finish(&mut self) -> Stop {
+        self.run(Resume::Finish)
+    }
+
+    pub fn cont(&mut self) -> Stop {
+        self.run(Resume::Cont)
+    }
+}
@@ -0,0 +1,303 @@
+use crate::lexer::{FileId, LexPos, LexPosType};
+use crate::source::Sources;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    pub fn str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ErrorCode {
+    CannotOpenFile,
+    BadToken,
+    DuplicateParameter,
+    DuplicateMacro,
+    CannotInclude,
+    IncludeCycle,
+    ExpectedArgument,
+    ExpectedRegister,
+    ExpectedLabel,
+    ExpectedNonMacroArgument,
+    CannotExpand,
+    MacroNotFound,
+    ExtraArgument,
+    DuplicateLabel,
+    LabelNotFound,
+    UnknownPragma,
+    CannotReadFile,
+    CodeInInclude,
+}
+
+impl ErrorCode {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorCode::CannotOpenFile => "E0001",
+            ErrorCode::BadToken => "E0002",
+            ErrorCode::DuplicateParameter => "E0003",
+            ErrorCode::DuplicateMacro => "E0004",
+            ErrorCode::CannotInclude => "E0005",
+            ErrorCode::IncludeCycle => "E0006",
+            ErrorCode::ExpectedArgument => "E0007",
+            ErrorCode::ExpectedRegister => "E0008",
+            ErrorCode::ExpectedLabel => "E0009",
+            ErrorCode::ExpectedNonMacroArgument => "E0010",
+            ErrorCode::CannotExpand => "E0011",
+            ErrorCode::MacroNotFound => "E0012",
+            ErrorCode::ExtraArgument => "E0013",
+            ErrorCode::DuplicateLabel => "E0014",
+            ErrorCode::LabelNotFound => "E0015",
+            ErrorCode::UnknownPragma => "E0016",
+            ErrorCode::CannotReadFile => "E0017",
+            ErrorCode::CodeInInclude => "E0018",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Span {
+    pos: LexPos,
+    len: LexPosType
+}
+
+impl Span {
+    pub fn new(pos: LexPos, len: LexPosType) -> Self {
+        Span {
+            pos: pos,
+            len: len
+        }
+    }
+
+    pub fn pos(&self) -> LexPos {
+        self.pos
+    }
+
+    pub fn file(&self) -> FileId {
+        self.pos.file()
+    }
+
+    pub fn line(&self) -> LexPosType {
+        self.pos.line()
+    }
+
+    pub fn sym(&self) -> LexPosType {
+        self.pos.sym()
+    }
+
+    pub fn len(&self) -> LexPosType {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Label {
+    pub span: Span,
+    pub message: String
+}
+
+#[derive(Clone, Debug)]
+pub struct MacroCall {
+    pub name: String,
+    pub span: Span
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    severity: Severity,
+    code: ErrorCode,
+    message: String,
+    span: Option<Span>,
+    labels: Vec<Label>,
+    notes: Vec<String>,
+    expansion: Vec<MacroCall>
+}
+
+impl Diagnostic {
+    pub fn error(code: ErrorCode, message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: code,
+            message: message,
+            span: None,
+            labels: Vec::new(),
+            notes: Vec::new(),
+            expansion: Vec::new()
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: String) -> Self {
+        self.labels.push(Label { span: span, message: message });
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    // chain of macro invocations that produced the erroneous code, innermost first
+    pub fn with_expansion(mut self, expansion: Vec<MacroCall>) -> Self {
+        self.expansion = expansion;
+        self
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    pub fn notes(&self) -> &[String] {
+        &self.notes
+    }
+
+    pub fn expansion(&self) -> &[MacroCall] {
+        &self.expansion
+    }
+
+    pub fn render(&self, sources: &Sources, colour: bool) -> String {
+        let style = Style { colour: colour };
+        let sev_colour = match self.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+            Severity::Note => CYAN,
+        };
+
+        let gutter = self.span.iter()
+            .chain(self.labels.iter().map(|l| &l.span))
+            .map(|s| s.line().to_string().len())
+            .max()
+            .unwrap_or(0);
+
+        let mut out = format!(
+            "{}{}",
+            style.paint(sev_colour, &format!("{}[{}]", self.severity.str(), self.code.code())),
+            style.paint(BOLD, &format!(": {}", self.message))
+        );
+
+        if let Some(span) = self.span {
+            render_snippet(&mut out, sources, &style, gutter, "-->", span, '^', sev_colour, "");
+        }
+
+        for label in self.labels.iter() {
+            render_snippet(&mut out, sources, &style, gutter, ":::", label.span, '-', BLUE, &label.message);
+        }
+
+        for note in self.notes.iter() {
+            out += &format!("\n{} {} {}", " ".repeat(gutter), style.paint(BLUE, "="), style.paint(BOLD, &format!("note: {}", note)));
+        }
+
+        for (i, call) in self.expansion.iter().enumerate() {
+            let pos = sources.pos_str(call.span.pos());
+
+            if i == 0 {
+                out += &format!("\n{} {} {} in expansion of `{}` at {}", " ".repeat(gutter), style.paint(BLUE, "="), style.paint(BOLD, "note:"), call.name, pos);
+            }
+            else {
+                out += &format!("\n{}         called from `{}` at {}", " ".repeat(gutter), call.name, pos);
+            }
+        }
+
+        out
+    }
+}
+
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const CYAN: &str = "\x1b[1;36m";
+const RESET: &str = "\x1b[0m";
+const TAB_WIDTH: usize = 4;
+
+struct Style {
+    colour: bool
+}
+
+impl Style {
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.colour {
+            format!("{}{}{}", code, text, RESET)
+        }
+        else {
+            String::from(text)
+        }
+    }
+}
+
+// Renders `--> file:line:col` followed by the source line and an underline below the span
+#[allow(clippy::too_many_arguments)]
+fn render_snippet(out: &mut String, sources: &Sources, style: &Style, gutter: usize, arrow: &str, span: Span, mark: char, colour: &str, message: &str) {
+    let pad = " ".repeat(gutter);
+    *out += &format!("\n{}{} {}", pad, style.paint(BLUE, arrow), sources.pos_str(span.pos()));
+
+    let Some(line) = sources.line(span.file(), span.line()) else {
+        if !message.is_empty() {
+            *out += &format!(" {}", style.paint(colour, message));
+        }
+        return;
+    };
+
+    // tabs are expanded, so the underline is aligned with the printed line
+    let mut shown = String::new();
+    let mut width = 0;
+    let mut start = None;
+    for (i, c) in line.chars().enumerate() {
+        if i + 1 == span.sym() as usize {
+            start = Some(width);
+        }
+
+        match c {
+            '\t' => {
+                shown += &" ".repeat(TAB_WIDTH);
+                width += TAB_WIDTH;
+            },
+            c => {
+                shown.push(c);
+                width += 1;
+            },
+        }
+    }
+    let start = start.unwrap_or(width);
+
+    let underline = format!("{}{}{}", " ".repeat(start), mark.to_string().repeat(span.len().max(1) as usize), if message.is_empty() { String::new() } else { format!(" {}", message) });
+
+    *out += &format!("\n{} {}", pad, style.paint(BLUE, "|"));
+    *out += &format!("\n{} {}", style.paint(BLUE, &format!("{:>width$}", span.line(), width = gutter)), style.paint(BLUE, "|"));
+    *out += &format!(" {}", shown);
+    *out += &format!("\n{} {} {}", pad, style.paint(BLUE, "|"), style.paint(colour, &underline));
+}
@@ -0,0 +1,50 @@
+use std::fs;
+
+use mur::bignat::BigNat;
+use mur::parser::Program;
+use mur::vm::Report;
+use serde_json::{Map, Value};
+
+// final values of the global registers with their source names, in order of allocation
+fn named_regs(program: &Program, report: &Report) -> Vec<(String, BigNat)> {
+    let mut names: Vec<_> = program.registers.iter().collect();
+    names.sort_by_key(|(name, r)| (**r, name.as_str()));
+
+    names.iter()
+        .map(|(name, r)| (format!("%{}", name), report.reg(**r)))
+        .collect()
+}
+
+pub fn print_regs(program: &Program, report: &Report) {
+    let regs = named_regs(program, report);
+    let width = regs.iter().map(|(name, _)| name.chars().count()).max().unwrap_or(0);
+
+    for (name, value) in regs.iter() {
+        eprintln!("{:<width$} = {}", name, value, width = width);
+    }
+}
+
+// numbers which don't fit in u64 are written as strings, the same `--input` accepts
+pub fn write_json(program: &Program, report: &Report, path: &str) -> Result<(), String> {
+    let mut map = Map::new();
+
+    for (name, value) in named_regs(program, report).into_iter() {
+        let value = match value.to_u64() {
+            Some(v) => Value::from(v),
+            None => Value::from(value.to_string()),
+        };
+
+        map.insert(String::from(&name[1..]), value);
+    }
+
+    let text = serde_json::to_string_pretty(&Value::Object(map)).unwrap();
+
+    if path == "-" {
+        println!("{}", text);
+    }
+    else if fs::write(path, text + "\n").is_err() {
+        return Err(format!("Cannot write registers to '{}'", path));
+    }
+
+    Ok(())
+}
@@ -1,6 +1,5 @@
-use std::io::BufRead;
+use std::io::{BufRead, Cursor};
 use std::rc::Rc;
-use std::{fs::File, io::BufReader};
 use std::collections::LinkedList;
 use regex::Regex;
 
@@ -8,21 +7,28 @@ const COMMENT_CHAR: char = '#';
 
 pub type LexPosType = u32;
 pub type LexStr = Rc<String>;
+pub type FileId = u32;
 
 #[derive(Clone, Copy, Debug)]
 pub struct LexPos {
+    file: FileId,
     line: LexPosType,
     sym: LexPosType
 }
 
 impl LexPos {
-    pub fn new(line: LexPosType, sym: LexPosType) -> Self {
+    pub fn new(file: FileId, line: LexPosType, sym: LexPosType) -> Self {
         LexPos {
+            file: file,
             line: line,
             sym: sym
         }
     }
 
+    pub fn file(&self) -> FileId {
+        self.file
+    }
+
     pub fn str(&self) -> String {
         format!("{}:{}", self.line, self.sym)
     }
@@ -51,8 +57,11 @@ pub enum Token {
     InvSlash(LexPos),
     
     Macro(LexPos),
+    Include(LexPos),
+    Pragma(LexPos),
 
     Id(LexStr, LexPos),
+    Str(LexStr, LexPos),
     Unknown(LexPos),
 }
 
@@ -71,11 +80,53 @@ impl Token {
             | Token::Pipe(pos)
             | Token::InvSlash(pos)
             | Token::Macro(pos)
+            | Token::Include(pos)
+            | Token::Pragma(pos)
             | Token::Id(_, pos)
+            | Token::Str(_, pos)
             | Token::Unknown(pos) => format!("{}:{}", pos.line(), pos.sym()),
         }
     }
 
+    pub fn pos(&self) -> Option<LexPos> {
+        match self {
+            Token::None
+            | Token::Eof => None,
+
+            Token::NewLine(pos)
+            | Token::Dot(pos)
+            | Token::At(pos)
+            | Token::Percent(pos)
+            | Token::BrOpen(pos)
+            | Token::BrClose(pos)
+            | Token::Pipe(pos)
+            | Token::InvSlash(pos)
+            | Token::Macro(pos)
+            | Token::Include(pos)
+            | Token::Pragma(pos)
+            | Token::Id(_, pos)
+            | Token::Str(_, pos)
+            | Token::Unknown(pos) => Some(*pos),
+        }
+    }
+
+    pub fn len(&self) -> LexPosType {
+        match self {
+            Token::None
+            | Token::Eof => 0,
+            Token::Macro(_) => 5,
+            Token::Include(_) => 7,
+            Token::Pragma(_) => 6,
+            Token::Id(name, _) => name.chars().count() as LexPosType,
+            Token::Str(s, _) => s.chars().count() as LexPosType + 2,
+            _ => 1,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn str(&self) -> String {
         match self {
             Token::None => String::from("None"),
@@ -90,16 +141,23 @@ impl Token {
             Token::InvSlash(_) => String::from("'\\'"),
             Token::Percent(_) => String::from("'%'"),
             Token::Macro(_) => String::from("'macro'"),
+            Token::Include(_) => String::from("'include'"),
+            Token::Pragma(_) => String::from("'pragma'"),
             Token::Id(name, _) => format!("Id({})", name),
+            Token::Str(s, _) => format!("Str(\"{}\")", s),
         }
     }
 }
 
 
 pub struct Lexer {
-    file_reader: BufReader<File>,
+    reader: Box<dyn BufRead>,
+    file: FileId,
+    file_name: LexStr,
+    lines: Vec<String>,
     token_buffer: LinkedList<Token>, // read whole line and place to buffer
     line: LexPosType,
+    read_error: Option<String>, // the file is read no further after an error
 
     curr_token: Token,
 
@@ -107,11 +165,19 @@ pub struct Lexer {
 }
 
 impl Lexer {
-    pub fn new(file: File) -> Self {
+    pub fn new(reader: impl BufRead + 'static) -> Self {
+        Lexer::with_file(reader, 0, "<input>")
+    }
+
+    pub fn with_file(reader: impl BufRead + 'static, file: FileId, file_name: &str) -> Self {
         Lexer {
-            file_reader: BufReader::new(file),
+            reader: Box::new(reader),
+            file: file,
+            file_name: Rc::new(String::from(file_name)),
+            lines: Vec::new(),
             token_buffer: LinkedList::new(),
             line: 0,
+            read_error: None,
 
             curr_token: Token::None,
 
@@ -119,16 +185,52 @@ impl Lexer {
         }
     }
 
+    pub fn from_str(source: &str) -> Self {
+        Lexer::new(Cursor::new(String::from(source)))
+    }
+
+    pub fn file(&self) -> FileId {
+        self.file
+    }
+
+    pub fn file_name(&self) -> &LexStr {
+        &self.file_name
+    }
+
+    pub fn take_lines(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.lines)
+    }
+
+    // position just after the last read line, used for errors at the end of file
+    pub fn eof_pos(&self) -> LexPos {
+        LexPos::new(self.file, self.line + 1, 1)
+    }
+
+    pub fn read_error(&self) -> Option<&str> {
+        self.read_error.as_deref()
+    }
+
     fn read_line(&mut self) {
         let mut line_buffer = String::new();
 
-        self.file_reader.read_line(&mut line_buffer).unwrap();
+        if self.read_error.is_some() {
+            self.token_buffer.push_back(Token::Eof);
+            return;
+        }
+
+        if let Err(err) = self.reader.read_line(&mut line_buffer) {
+            self.read_error = Some(err.to_string());
+            self.token_buffer.push_back(Token::Eof);
+            return;
+        }
 
         if line_buffer.is_empty() {
             self.token_buffer.push_back(Token::Eof);
             return;
         };       
 
+        self.lines.push(String::from(line_buffer.trim_end_matches(['\n', '\r'])));
+
         let mut line = line_buffer.as_str();
         if let Some(i) = line.find(COMMENT_CHAR) {
             line = &line[..i];
@@ -143,51 +245,69 @@ impl Lexer {
                 line = &line[1..];
             }
             else if line.starts_with(';') {
-                self.token_buffer.push_back(Token::NewLine(LexPos::new(self.line, sym_no)));
+                self.token_buffer.push_back(Token::NewLine(LexPos::new(self.file, self.line, sym_no)));
                 sym_no += 1;
                 line = &line[1..];
             }
             else if line.starts_with('.') {
-                self.token_buffer.push_back(Token::Dot(LexPos::new(self.line, sym_no)));
+                self.token_buffer.push_back(Token::Dot(LexPos::new(self.file, self.line, sym_no)));
                 sym_no += 1;
                 line = &line[1..];
             }
             else if line.starts_with('@') {
-                self.token_buffer.push_back(Token::At(LexPos::new(self.line, sym_no)));
+                self.token_buffer.push_back(Token::At(LexPos::new(self.file, self.line, sym_no)));
                 sym_no += 1;
                 line = &line[1..];
             }
             else if line.starts_with('%') {
-                self.token_buffer.push_back(Token::Percent(LexPos::new(self.line, sym_no)));
+                self.token_buffer.push_back(Token::Percent(LexPos::new(self.file, self.line, sym_no)));
                 sym_no += 1;
                 line = &line[1..];
             }
             else if line.starts_with('{') {
-                self.token_buffer.push_back(Token::BrOpen(LexPos::new(self.line, sym_no)));
+                self.token_buffer.push_back(Token::BrOpen(LexPos::new(self.file, self.line, sym_no)));
                 sym_no += 1;
                 line = &line[1..];
             }
             else if line.starts_with('}') {
-                self.token_buffer.push_back(Token::BrClose(LexPos::new(self.line, sym_no)));
+                self.token_buffer.push_back(Token::BrClose(LexPos::new(self.file, self.line, sym_no)));
                 sym_no += 1;
                 line = &line[1..];
             }
             else if line.starts_with('|') {
-                self.token_buffer.push_back(Token::Pipe(LexPos::new(self.line, sym_no)));
+                self.token_buffer.push_back(Token::Pipe(LexPos::new(self.file, self.line, sym_no)));
                 sym_no += 1;
                 line = &line[1..];
             }
             else if line.starts_with('\\') {
-                self.token_buffer.push_back(Token::InvSlash(LexPos::new(self.line, sym_no)));
+                self.token_buffer.push_back(Token::InvSlash(LexPos::new(self.file, self.line, sym_no)));
                 sym_no += 1;
                 line = &line[1..];
             }
+            else if line.starts_with('"') {
+                let Some(end) = line[1..].find('"') else {
+                    // unterminated string takes the rest of the line
+                    self.token_buffer.push_back(Token::Unknown(LexPos::new(self.file, self.line, sym_no)));
+                    sym_no += line.trim_end().chars().count() as u32;
+                    line = "";
+                    continue;
+                };
+
+                let s = &line[1..end + 1];
+                self.token_buffer.push_back(Token::Str( Rc::new(String::from(s)), LexPos::new(self.file, self.line, sym_no) ));
+
+                let d = s.chars().count() + 2;
+                sym_no += d as u32;
+                line = &line[end + 2..];
+            }
             else if let Some(caps) = self.id_regex.captures(line) {
                 let id = &caps[0];
-                let pos = LexPos::new(self.line, sym_no);
+                let pos = LexPos::new(self.file, self.line, sym_no);
 
                 match id {
                     "macro" => self.token_buffer.push_back(Token::Macro(pos)),
+                    "include" => self.token_buffer.push_back(Token::Include(pos)),
+                    "pragma" => self.token_buffer.push_back(Token::Pragma(pos)),
                     other => self.token_buffer.push_back(Token::Id( Rc::new(String::from(other)), pos ))
                 }
 
@@ -196,12 +316,15 @@ impl Lexer {
                 line = &line[d..];
             }
             else {
-                self.token_buffer.push_back(Token::Unknown(LexPos::new(self.line, sym_no)));
-                return;
+                // the rest of the line is still read, so the parser can recover after the bad symbol
+                self.token_buffer.push_back(Token::Unknown(LexPos::new(self.file, self.line, sym_no)));
+                let d = line.chars().next().unwrap().len_utf8();
+                sym_no += 1;
+                line = &line[d..];
             }
         }
 
-        self.token_buffer.push_back(Token::NewLine(LexPos::new(self.line, sym_no)));
+        self.token_buffer.push_back(Token::NewLine(LexPos::new(self.file, self.line, sym_no)));
     }
 
     pub fn next(&mut self) -> Token {
@@ -0,0 +1,15 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names, clippy::should_implement_trait)]
+
+pub mod coverage;
+pub mod debug;
+pub mod diagnostic;
+pub mod lexer;
+pub mod meta;
+pub mod meta2;
+pub mod optimize;
+pub mod parser;
+pub mod profile;
+pub mod source;
+pub mod source_map;
+pub mod bignat;
+pub mod vm;
@@ -1,57 +1,229 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
 mod cli_parser;
-mod lexer;
-mod meta;
-mod meta2;
-mod parser;
-mod bignat;
-mod vm;
-
-use std::{fs::File, path::Path};
-use cli_parser::CmdArgs;
-use lexer::Lexer;
-use parser::Parser;
-use vm::execute;
+mod dap;
+mod dump;
+mod presets;
+mod repl;
+mod trace;
+
+use std::fs;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::process::exit;
+use cli_parser::{CmdArgs, Command};
+use presets::Presets;
+use repl::Repl;
+use trace::Tracer;
+use mur::coverage::Coverage;
+use mur::debug::Debugger;
+use mur::diagnostic::Diagnostic;
+use mur::optimize;
+use mur::parser::{Parser, Program};
+use mur::profile::Profile;
+use mur::source::Sources;
+use mur::vm::{execute_fused, execute_observed, Halt, Input, Machine, Op, Output, Report, RuntimeError};
+
+const EXIT_COMPILE_ERROR: i32 = 1;
+const EXIT_LIMIT_EXCEEDED: i32 = 2;
+const EXIT_RUNTIME_ERROR: i32 = 3;
+const EXIT_USAGE_ERROR: i32 = 4;
+
+fn report(errors: &[Diagnostic], sources: &Sources, colour: bool) {
+    for diag in errors.iter() {
+        eprintln!("{}\n", diag.render(sources, colour));
+    }
+
+    eprintln!("{} error(s) found", errors.len());
+}
+
+fn report_stop(report: &Report, program: &Program, sources: &Sources, reason: &str) {
+    eprintln!("Execution stopped ({}) after {} steps at instruction #{} {}", reason, report.steps, report.ip, program.source_map.pos_str(report.ip, sources));
+
+    for call in program.source_map.stack(report.ip).iter() {
+        eprintln!("    in expansion of `{}` at {}", call.name, sources.pos_str(call.span.pos()));
+    }
+}
+
+// `-` is stdout
+fn write_file(path: &str, text: &str, what: &str) {
+    if path == "-" {
+        print!("{}", text);
+    }
+    else if fs::write(path, text).is_err() {
+        eprintln!("Cannot write {} to '{}'", what, path);
+    }
+}
 
 fn main() {
     let args = match CmdArgs::parse(std::env::args()) {
         Ok(t) => t,
         Err(msg) => {
-            println!("{}", msg);
-            return;
+            eprintln!("{}", msg);
+            exit(EXIT_USAGE_ERROR);
         }
     };
 
+    if args.command() == Command::Dap {
+        dap::serve();
+        return;
+    }
+
+    // diagnostics go to stderr, apart from the output of the program
+    let colour = std::io::stderr().is_terminal();
     let path = Path::new(args.filepath());
 
     if !path.exists() {
-        println!("File doesn't exist");
-        return;
+        eprintln!("File doesn't exist");
+        exit(EXIT_COMPILE_ERROR);
     }
 
     if !path.is_file() {
-        println!("Specified path is not file");
-        return;
+        eprintln!("Specified path is not file");
+        exit(EXIT_COMPILE_ERROR);
     }
 
-    let Ok(file) = File::open(args.filepath()) else {
-        println!("Cannot open file");
-        return;
+    let mut parser = match Parser::open(path) {
+        Ok(p) => p,
+        Err(diag) => {
+            eprintln!("{}", diag.render(&Sources::new(), colour));
+            exit(EXIT_COMPILE_ERROR);
+        }
     };
 
-    let lexer = Lexer::new(file);
-    let mut parser = Parser::new(lexer);
+    parser.set_max_errors(args.max_errors());
+    parser.set_optimize(args.optimize());
 
-    if args.only_expand() {
-        if let Err(msg) = parser.print_debug() {
-            println!("{}", msg);
+    let mut presets = Presets::new();
+
+    if let Some(file) = args.input_file() {
+        if let Err(msg) = presets.load(file) {
+            eprintln!("{}", msg);
+            exit(EXIT_USAGE_ERROR);
+        }
+    }
+
+    for (name, value) in args.presets().iter() {
+        presets.add(name, value);
+    }
+
+    parser.set_inputs(presets.names());
+
+    if args.command() == Command::Check {
+        let errors = parser.check();
+
+        if !errors.is_empty() {
+            report(&errors, parser.sources(), colour);
+            exit(EXIT_COMPILE_ERROR);
+        }
+    }
+    else if args.only_expand() {
+        if let Err(errors) = parser.print_debug() {
+            report(&errors, parser.sources(), colour);
+            exit(EXIT_COMPILE_ERROR);
         }
     }
     else {
         match parser.parse() {
-            Ok(code) => execute(code),
-            Err(msg) => {
-                println!("{}", msg);
-                return;
+            Ok(program) => {
+                if args.optimize() {
+                    let removed = parser.removed();
+                    eprintln!("Peephole pass removed {} of {} instructions", removed, program.code.len() + removed);
+                }
+
+                let regs = match presets.registers(&program) {
+                    Ok(regs) => regs,
+                    Err(msg) => {
+                        eprintln!("{}", msg);
+                        exit(EXIT_USAGE_ERROR);
+                    }
+                };
+
+                let out_mode = args.out_mode().or(program.pragmas.out_mode).unwrap_or_default();
+                if args.command() == Command::Debug {
+                    // commands of the debugger are read through the program's input, so the two share one buffer
+                    let input = Input::stdin(args.input_mode());
+                    let machine = Machine::new(&program.code, regs, input, Output::stdout(out_mode));
+
+                    Repl::new(Debugger::new(&program, machine), parser.sources()).run();
+                    return;
+                }
+
+                let input = Input::stdin(args.input_mode());
+                let output = Output::stdout(out_mode);
+
+                let tracer = args.trace().map(|options| Tracer::new(&program, parser.sources(), options));
+                let profile = args.counting().then(|| Profile::new(&program));
+
+                let mut observers = (tracer, profile);
+                let report = match observers {
+                    (None, None) => {
+                        // observers see every step, so only unobserved runs have fused loops
+                        execute_fused(&optimize::fuse_loops(&program.code), regs, args.limits(), input, output)
+                    },
+                    _ => execute_observed(&program.code, regs, args.limits(), input, output, &mut observers),
+                };
+
+                // the tracer writes its buffered lines when dropped, before the profile is printed
+                let (tracer, profile) = observers;
+                drop(tracer);
+
+                if let Some(profile) = profile {
+                    if args.profile() {
+                        eprint!("{}", profile.render(&program, parser.sources()));
+                    }
+
+                    if let Some(path) = args.profile_folded() {
+                        write_file(path, &profile.folded(&program), "profile");
+                    }
+
+                    let coverage = Coverage::new(&profile, &program);
+
+                    if args.coverage() {
+                        eprint!("{}", coverage.render(parser.sources()));
+                    }
+
+                    if let Some(path) = args.coverage_lcov() {
+                        write_file(path, &coverage.lcov(parser.sources()), "coverage");
+                    }
+                }
+
+                if args.dump_regs() {
+                    dump::print_regs(&program, &report);
+                }
+
+                if let Some(path) = args.dump_json() {
+                    if let Err(msg) = dump::write_json(&program, &report, path) {
+                        eprintln!("{}", msg);
+                    }
+                }
+
+                match report.halt {
+                    Halt::End => (),
+                    Halt::StepLimit => {
+                        report_stop(&report, &program, parser.sources(), "step limit exceeded");
+                        exit(EXIT_LIMIT_EXCEEDED);
+                    },
+                    Halt::Timeout => {
+                        report_stop(&report, &program, parser.sources(), "timeout");
+                        exit(EXIT_LIMIT_EXCEEDED);
+                    },
+                    Halt::Error(err) => {
+                        let reason = match (err, program.code.get(report.ip)) {
+                            (RuntimeError::NotChar, Some(Op::Out(r))) => format!("{} is not a character", report.reg(*r)),
+                            (RuntimeError::NotByte, Some(Op::Out(r))) => format!("{} is not a byte", report.reg(*r)),
+                            (RuntimeError::BadOutput, _) => String::from("cannot write output"),
+                            _ => String::from("bad input"),
+                        };
+
+                        report_stop(&report, &program, parser.sources(), &reason);
+                        exit(EXIT_RUNTIME_ERROR);
+                    },
+                }
+            },
+            Err(errors) => {
+                report(&errors, parser.sources(), colour);
+                exit(EXIT_COMPILE_ERROR);
             },
         }
     }
@@ -1,7 +1,13 @@
 use std::collections::{HashMap, LinkedList};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
-use crate::lexer::{LexPos, LexStr, Lexer, Token};
+use crate::diagnostic::{Diagnostic, ErrorCode, Span};
+use crate::lexer::{FileId, LexPos, LexPosType, LexStr, Lexer, Token};
+use crate::source::Sources;
+use crate::vm::OutputMode;
 
 pub type IsLocal = bool;
 pub type NonLocalSearch = bool;
@@ -15,12 +21,21 @@ pub enum MetaArg {
 }
 
 impl MetaArg {
-    pub fn pos_str(&self) -> String {
+    pub fn pos(&self) -> LexPos {
         match self {
             MetaArg::Reg(_, _, _, pos)
             | MetaArg::Lab(_, _, pos)
             | MetaArg::Id(_, pos)
-            | MetaArg::Code(_, _, pos) => pos.str(),
+            | MetaArg::Code(_, _, pos) => *pos,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            MetaArg::Reg(name, _, _, pos)
+            | MetaArg::Id(name, pos) => Span::new(*pos, name.chars().count() as LexPosType),
+            MetaArg::Lab(name, is_local, pos) => Span::new(*pos, name.chars().count() as LexPosType + if *is_local { 2 } else { 1 }),
+            MetaArg::Code(_, _, pos) => Span::new(*pos, 1),
         }
     }
 
@@ -45,115 +60,167 @@ pub enum Meta {
 pub type CodeMeta = LinkedList<Meta>;
 pub type MacroData = (LinkedList<LexStr>, CodeMeta);
 
+// settings given in the source with `pragma name value`
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Pragmas {
+    pub out_mode: Option<OutputMode>
+}
+
 pub struct ParsMetaResult {
     pub code: CodeMeta,
-    pub macros: HashMap<LexStr, MacroData>
+    pub macros: HashMap<LexStr, MacroData>,
+    pub pragmas: Pragmas
+}
+
+fn bad_token(lexer: &Lexer, tok: Token) -> Diagnostic {
+    let pos = tok.pos().unwrap_or_else(|| lexer.eof_pos());
+    Diagnostic::error(ErrorCode::BadToken, format!("Bad token: {}", tok.str())).with_span(Span::new(pos, tok.len()))
 }
 
-fn bad_token(tok: Token) -> String {
-    format!("{} Bad token: {}", tok.pos_str(), tok.str())
+fn duplicate_parameter(name: &LexStr, pos: LexPos) -> Diagnostic {
+    Diagnostic::error(ErrorCode::DuplicateParameter, format!("Parameter with name '{}' is already exist", name))
+        .with_span(Span::new(pos, name.chars().count() as LexPosType))
 }
 
-fn parse_arg_reg(lexer: &mut Lexer) -> Result<MetaArg, String> {
+// Skips the rest of a broken statement together with any blocks opened in it.
+// Stops at a new line, an unmatched closing brace or EOF and returns that token.
+fn recover(lexer: &mut Lexer) -> Token {
+    let mut depth = 0;
+    let mut tok = lexer.curr();
+
+    loop {
+        match tok {
+            Token::BrOpen(_) => depth += 1,
+            Token::BrClose(_) if depth > 0 => depth -= 1,
+
+            Token::BrClose(_)
+            | Token::Eof => return tok,
+            Token::NewLine(_) if depth == 0 => return tok,
+
+            _ => (),
+        }
+
+        tok = lexer.next();
+    }
+}
+
+// Parses statements up to the closing brace, broken statements are reported and skipped
+fn parse_block(lexer: &mut Lexer, errors: &mut Vec<Diagnostic>) -> CodeMeta {
+    let mut meta = CodeMeta::new();
+
+    loop {
+        let res = match lexer.next() {
+            Token::NewLine(_) => continue,
+            Token::BrClose(_) => break,
+            
+            Token::Id(name, pos) => parse_op(lexer, name, pos, errors),
+            Token::At(pos) => parse_label(lexer, pos),
+
+            tok => Err(bad_token(lexer, tok)),
+        };
+
+        match res {
+            Ok(m) => meta.push_back(m),
+            Err(diag) => {
+                errors.push(diag);
+
+                if let Token::BrClose(_) | Token::Eof = recover(lexer) {
+                    break;
+                }
+            },
+        }
+    }
+
+    meta
+}
+
+fn parse_arg_reg(lexer: &mut Lexer) -> Result<MetaArg, Diagnostic> {
     match lexer.next() {       
         Token::Id(name, pos) => return Ok(MetaArg::Reg(name, false, false, pos)),
         Token::Dot(_) => match lexer.next() {
             Token::Id(name, pos) => Ok(MetaArg::Reg(name, true, false, pos)),
-            tok => Err(bad_token(tok)),
+            tok => Err(bad_token(lexer, tok)),
         },
         Token::InvSlash(_) => match lexer.next() {
             Token::Dot(_) => match lexer.next() {
                 Token::Id(name, pos) => Ok(MetaArg::Reg(name, true, true, pos)),
-            tok => Err(bad_token(tok)),
+            tok => Err(bad_token(lexer, tok)),
             },
-            tok => Err(bad_token(tok))
+            tok => Err(bad_token(lexer, tok))
         }
-        tok => return Err(bad_token(tok)),
+        tok => return Err(bad_token(lexer, tok)),
     }
 }
 
-fn parse_arg_lab(lexer: &mut Lexer, pos: LexPos) -> Result<MetaArg, String> {
+fn parse_arg_lab(lexer: &mut Lexer, pos: LexPos) -> Result<MetaArg, Diagnostic> {
     match lexer.next() {       
         Token::Id(name, _) => return Ok(MetaArg::Lab(name, false, pos)),
         Token::Dot(_) => (),
-        tok => return Err(bad_token(tok)),
+        tok => return Err(bad_token(lexer, tok)),
     }
 
     match lexer.next() {        
         Token::Id(name, _) => Ok(MetaArg::Lab(name, true, pos)),
-        tok => Err(bad_token(tok)),
+        tok => Err(bad_token(lexer, tok)),
     }
 }
 
-fn parse_arg_code(lexer: &mut Lexer, pos: LexPos) -> Result<MetaArg, String> {
+fn parse_arg_code(lexer: &mut Lexer, pos: LexPos, errors: &mut Vec<Diagnostic>) -> MetaArg {
     let mut ids = LinkedList::new();
-    let mut meta = CodeMeta::new();
 
     loop {
         match lexer.next() {            
             Token::Pipe(_) => break,
-            Token::Id(name, _) => {
+            Token::Id(name, pos) => {
                 if ids.contains(&name) {
-                    return Err(format!("{} Parameter with name '{}' is already exist", pos.str(), name));
+                    errors.push(duplicate_parameter(&name, pos));
                 }
                 ids.push_back(name);
             },
 
-            tok => return Err(bad_token(tok)),
-        }
-    }
+            tok => {
+                // the block is skipped as a whole, so its closing brace isn't taken for the outer one
+                errors.push(bad_token(lexer, tok));
 
-    loop {
-        match lexer.next() {
-            Token::NewLine(_) => continue,
-            Token::BrClose(_) => break,
-            
-            Token::Id(name, pos) => meta.push_back(parse_op(lexer, name, pos)?),
-            Token::At(pos) => meta.push_back(parse_label(lexer, pos)?),
+                while let Token::NewLine(_) = recover(lexer) {
+                    lexer.next();
+                }
 
-            tok => return Err(bad_token(tok)),
+                return MetaArg::Code(ids, CodeMeta::new(), pos);
+            },
         }
     }
 
-    Ok(MetaArg::Code(ids, meta, pos))
+    MetaArg::Code(ids, parse_block(lexer, errors), pos)
 }
 
-fn parse_macro(lexer: &mut Lexer) -> Result<(LexStr, MacroData), String> {
+fn parse_macro(lexer: &mut Lexer, errors: &mut Vec<Diagnostic>) -> Result<(LexStr, MacroData), Diagnostic> {
     let name = match lexer.next() {
         Token::Id(name, _) => name,
-        tok => return Err(bad_token(tok)),
+        tok => return Err(bad_token(lexer, tok)),
     };
 
     let mut args = LinkedList::new();
-    let mut meta = CodeMeta::new();
 
     loop {
         match lexer.next() {
             Token::Id(name, pos) => { 
                 if args.contains(&name) {
-                    return Err(format!("{} Parameter with name '{}' is already exist", pos.str(), name));
+                    errors.push(duplicate_parameter(&name, pos));
                 }
                 args.push_back(name);
             },
             Token::BrOpen(_) => break,
-            tok => return Err(bad_token(tok)),
+            tok => return Err(bad_token(lexer, tok)),
         }
     }
 
-    loop {
-        match lexer.next() {
-            Token::Id(name, pos) => meta.push_back(parse_op(lexer, name, pos)?),
-            Token::At(pos) => meta.push_back(parse_label(lexer, pos)?),
-            Token::BrClose(_) => break,
-            Token::NewLine(_) => continue,
-            tok => return Err(bad_token(tok)),
-        }
-    }
+    let meta = parse_block(lexer, errors);
 
     Ok((name, (args, meta)))
 }
 
-fn parse_op(lexer: &mut Lexer, name: LexStr, pos: LexPos) -> Result<Meta, String> {
+fn parse_op(lexer: &mut Lexer, name: LexStr, pos: LexPos, errors: &mut Vec<Diagnostic>) -> Result<Meta, Diagnostic> {
     let mut args = MetaArgs::new();
 
     loop {
@@ -164,26 +231,26 @@ fn parse_op(lexer: &mut Lexer, name: LexStr, pos: LexPos) -> Result<Meta, String
             Token::Percent(_) => args.push_back(parse_arg_reg(lexer)?),
             Token::At(pos) => args.push_back(parse_arg_lab(lexer, pos)?),
             Token::Id(name, pos) => args.push_back(MetaArg::Id(name, pos)),
-            Token::BrOpen(pos) => args.push_back(parse_arg_code(lexer, pos)?),
+            Token::BrOpen(pos) => args.push_back(parse_arg_code(lexer, pos, errors)),
 
-            tok => return Err(bad_token(tok)),
+            tok => return Err(bad_token(lexer, tok)),
         }
     }
 
     Ok(Meta::Op(name, args, pos))
 }
 
-fn parse_label(lexer: &mut Lexer, pos: LexPos) -> Result<Meta, String> {
+fn parse_label(lexer: &mut Lexer, pos: LexPos) -> Result<Meta, Diagnostic> {
     match lexer.next() {       
         Token::Id(name, _) => {
             match lexer.next() {
                 Token::NewLine(_)
                 | Token::Eof => return Ok(Meta::Lab(name, false, pos)),
-                tok => return Err(bad_token(tok)),
+                tok => return Err(bad_token(lexer, tok)),
             }
         },
         Token::Dot(_) => (),
-        tok => return Err(bad_token(tok)),
+        tok => return Err(bad_token(lexer, tok)),
     }
 
     match lexer.next() {        
@@ -191,38 +258,213 @@ fn parse_label(lexer: &mut Lexer, pos: LexPos) -> Result<Meta, String> {
             match lexer.next() {
                 Token::NewLine(_)
                 | Token::Eof => return Ok(Meta::Lab(name, true, pos)),
-                tok => return Err(bad_token(tok)),
+                tok => return Err(bad_token(lexer, tok)),
             }
         },
-        tok => return Err(bad_token(tok)),
+        tok => return Err(bad_token(lexer, tok)),
     }
 }
 
-pub fn parse_to_meta(lexer: &mut Lexer) -> Result<ParsMetaResult, String> {
-    let mut meta = LinkedList::new();
-    let mut macros = HashMap::new();
+fn cannot_include(name: &LexStr, pos: LexPos, err: std::io::Error) -> Diagnostic {
+    Diagnostic::error(ErrorCode::CannotInclude, format!("Cannot include '{}': {}", name, err)).with_span(Span::new(pos, 7))
+}
+
+fn parse_include(lexer: &mut Lexer, sources: &mut Sources, stack: &mut Vec<FileId>, res: &mut ParsMetaResult, errors: &mut Vec<Diagnostic>, pos: LexPos) -> Result<(), Diagnostic> {
+    let name = match lexer.next() {
+        Token::Str(name, _) => name,
+        tok => return Err(bad_token(lexer, tok)),
+    };
 
+    match lexer.next() {
+        Token::NewLine(_)
+        | Token::Eof => (),
+        tok => return Err(bad_token(lexer, tok)),
+    }
+
+    // paths are relative to the including file, or to the working directory for in-memory sources
+    let (path, display_path) = match sources.path(lexer.file()).and_then(|p| p.parent()) {
+        Some(dir) => (
+            dir.join(name.as_str()),
+            Path::new(lexer.file_name().as_str()).with_file_name(name.as_str())
+        ),
+        None => (PathBuf::from(name.as_str()), PathBuf::from(name.as_str())),
+    };
+
+    let full_path = match fs::canonicalize(&path) {
+        Ok(p) => p,
+        Err(err) => return Err(cannot_include(&name, pos, err)),
+    };
+
+    if let Some(i) = stack.iter().position(|f| sources.path(*f) == Some(full_path.as_path())) {
+        let chain: Vec<&str> = stack[i..].iter().map(|f| sources.name(*f)).collect();
+        return Err(
+            Diagnostic::error(ErrorCode::IncludeCycle, format!("Include cycle: {} -> {}", chain.join(" -> "), sources.name(stack[i])))
+                .with_span(Span::new(pos, 7))
+        );
+    }
+
+    // every file is included only once, so shared libraries can be included from several places
+    if sources.find(&full_path).is_some() {
+        return Ok(());
+    }
+
+    let file = match File::open(&full_path) {
+        Ok(f) => f,
+        Err(err) => return Err(cannot_include(&name, pos, err)),
+    };
+
+    let path_str = display_path.to_string_lossy();
+    let id = sources.add(&path_str, Some(full_path));
+    let mut inc_lexer = Lexer::with_file(BufReader::new(file), id, &path_str);
+
+    stack.push(id);
+    parse_file(&mut inc_lexer, sources, stack, res, errors);
+    stack.pop();
+
+    Ok(())
+}
+
+fn parse_pragma(lexer: &mut Lexer, res: &mut ParsMetaResult) -> Result<(), Diagnostic> {
+    let (name, name_pos) = match lexer.next() {
+        Token::Id(name, pos) => (name, pos),
+        tok => return Err(bad_token(lexer, tok)),
+    };
+
+    let (value, value_pos) = match lexer.next() {
+        Token::Id(value, pos) => (value, pos),
+        tok => return Err(bad_token(lexer, tok)),
+    };
+
+    match lexer.next() {
+        Token::NewLine(_)
+        | Token::Eof => (),
+        tok => return Err(bad_token(lexer, tok)),
+    }
+
+    match name.as_str() {
+        "out" => {
+            let Some(mode) = OutputMode::from_name(&value) else {
+                return Err(
+                    Diagnostic::error(ErrorCode::UnknownPragma, format!("Unknown output mode '{}'", value))
+                        .with_span(Span::new(value_pos, value.chars().count() as LexPosType))
+                        .with_note(String::from("expected one of: char, byte, dec, hex"))
+                );
+            };
+
+            res.pragmas.out_mode = Some(mode);
+        },
+        _ => return Err(
+            Diagnostic::error(ErrorCode::UnknownPragma, format!("Unknown pragma '{}'", name))
+                .with_span(Span::new(name_pos, name.chars().count() as LexPosType))
+        ),
+    }
+
+    Ok(())
+}
+
+// only the main file has code, an included file gives its macros
+fn push_code(res: &mut ParsMetaResult, stack: &[FileId], meta: Meta) -> Result<(), Diagnostic> {
+    if stack.len() > 1 {
+        let span = match &meta {
+            Meta::Op(name, _, pos) => Span::new(*pos, name.chars().count() as LexPosType),
+            Meta::Lab(name, is_local, pos) => MetaArg::Lab(Rc::clone(name), *is_local, *pos).span(),
+        };
+
+        return Err(
+            Diagnostic::error(ErrorCode::CodeInInclude, String::from("Code outside of macros in an included file"))
+                .with_span(span)
+                .with_note(String::from("only the macros of included files are used"))
+        );
+    }
+
+    res.code.push_back(meta);
+    Ok(())
+}
+
+fn parse_item(lexer: &mut Lexer, sources: &mut Sources, stack: &mut Vec<FileId>, res: &mut ParsMetaResult, errors: &mut Vec<Diagnostic>, tok: Token) -> Result<(), Diagnostic> {
+    match tok {
+        Token::Id(name, pos) => push_code(res, stack, parse_op(lexer, name, pos, errors)?)?,
+        Token::At(pos) => push_code(res, stack, parse_label(lexer, pos)?)?,
+        Token::Macro(pos) => {
+            let (name, data) = parse_macro(lexer, errors)?;
+
+            if res.macros.insert(Rc::clone(&name), data).is_some() {
+                return Err(
+                    Diagnostic::error(ErrorCode::DuplicateMacro, format!("Macro with name '{}' is already exist", name))
+                        .with_span(Span::new(pos, 5))
+                );
+            }
+        },
+        Token::Include(pos) => parse_include(lexer, sources, stack, res, errors, pos)?,
+        Token::Pragma(_) => parse_pragma(lexer, res)?,
+
+        tok => return Err(bad_token(lexer, tok)),
+    }
+
+    Ok(())
+}
+
+fn parse_file(lexer: &mut Lexer, sources: &mut Sources, stack: &mut Vec<FileId>, res: &mut ParsMetaResult, errors: &mut Vec<Diagnostic>) {
     loop {
-        match lexer.next() {
+        let tok = match lexer.next() {
             Token::NewLine(_) => continue,
             Token::Eof => break,
-            
-            Token::Id(name, pos) => meta.push_back(parse_op(lexer, name, pos)?),
-            Token::At(pos) => meta.push_back(parse_label(lexer, pos)?),
-            Token::Macro(pos) => {
-                let (name, data) = parse_macro(lexer)?;
+            tok => tok,
+        };
 
-                if let Some(_) = macros.insert(Rc::clone(&name), data) {
-                    return Err(format!("{} Macro with name '{}' is already exist", pos.str(), name));
-                }
-            },
+        if let Err(diag) = parse_item(lexer, sources, stack, res, errors, tok) {
+            errors.push(diag);
 
-            tok => return Err(bad_token(tok)),
+            if let Token::Eof = recover(lexer) {
+                break;
+            }
         }
     }
 
-    Ok(ParsMetaResult {
-        code: meta,
-        macros: macros
-    })
-}
\ No newline at end of file
+    if let Some(err) = lexer.read_error() {
+        errors.push(
+            Diagnostic::error(ErrorCode::CannotReadFile, format!("Cannot read '{}': {}", lexer.file_name(), err))
+                .with_span(Span::new(lexer.eof_pos(), 1))
+        );
+    }
+
+    sources.set_lines(lexer.file(), lexer.take_lines());
+}
+
+// Broken statements are reported to `errors` and skipped, so the result holds everything that could be parsed
+pub fn parse_to_meta(lexer: &mut Lexer, sources: &mut Sources, errors: &mut Vec<Diagnostic>) -> ParsMetaResult {
+    let mut res = ParsMetaResult {
+        code: LinkedList::new(),
+        macros: HashMap::new(),
+        pragmas: Pragmas::default()
+    };
+
+    parse_file(lexer, sources, &mut vec![lexer.file()], &mut res, errors);
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn included_files_give_only_macros() {
+        let path = std::env::temp_dir().join(format!("mur-include-{}.mur", std::process::id()));
+        fs::write(&path, "macro twice X {\n    inc X\n    inc X\n}\n\ninc %x\n@l\ntwice %x\n").unwrap();
+
+        let mut lexer = Lexer::from_str(&format!("include \"{}\"\ntwice %y\n", path.display()));
+        let mut sources = Sources::new();
+        sources.add(lexer.file_name(), None);
+
+        let mut errors = Vec::new();
+        let res = parse_to_meta(&mut lexer, &mut sources, &mut errors);
+        fs::remove_file(&path).unwrap();
+
+        let lines: Vec<_> = errors.iter().map(|d| (d.code(), d.span().unwrap().line())).collect();
+        assert_eq!(lines, vec![(ErrorCode::CodeInInclude, 6), (ErrorCode::CodeInInclude, 7), (ErrorCode::CodeInInclude, 8)]);
+
+        assert!(res.macros.contains_key(&String::from("twice")));
+        assert_eq!(res.code.len(), 1);
+    }
+}
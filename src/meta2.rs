@@ -3,7 +3,8 @@ use std::collections::{HashMap, LinkedList};
 use std::hash::Hash;
 use std::rc::Rc;
 
-use crate::lexer::{LexPos, LexStr};
+use crate::diagnostic::{Diagnostic, ErrorCode, MacroCall, Span};
+use crate::lexer::{LexPos, LexPosType, LexStr};
 use crate::meta::{CodeMeta, IsLocal, MacroData, Meta, MetaArg, MetaArgs, NonLocalSearch};
 use crate::vm::CellType;
 
@@ -32,6 +33,10 @@ where T: Hash, T: Eq
         self.levels.pop_front().unwrap()
     }
 
+    pub fn global(&self) -> &HashMap<T, V> {
+        self.levels.back().unwrap()
+    }
+
     pub fn find(&self, name: &T) -> Option<&V> {
         for i in self.levels.iter() {
             if let Some(r) = i.get(name) {
@@ -60,7 +65,7 @@ where T: Hash, T: Eq
 
     pub fn put(&mut self, name: T, value: V) -> Result<(), ()> {
         let front = self.levels.front_mut().unwrap();
-        if let Some(_) = front.insert(name, value) {
+        if front.insert(name, value).is_some() {
             return Err(())
         }
 
@@ -68,7 +73,7 @@ where T: Hash, T: Eq
     }
 
     pub fn put_global(&mut self, name: T, value: V) -> Result<(), ()> {
-        if let Some(_) = self.levels.back_mut().unwrap().insert(name, value) {
+        if self.levels.back_mut().unwrap().insert(name, value).is_some() {
             return Err(())
         }
 
@@ -101,18 +106,138 @@ impl MetaId {
     }
 }
 
+pub type FrameId = usize;
+
+// One macro invocation, frames of nested invocations point to the frame they were expanded in
+#[derive(Debug, Clone)]
+pub struct Frame {
+    name: LexStr,
+    span: Span,
+    parent: Option<FrameId>,
+    locals: HashMap<LexStr, CellType> // registers `%.x` of the expansion, their cells are reused after it
+}
+
+impl Frame {
+    pub fn name(&self) -> &LexStr {
+        &self.name
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn parent(&self) -> Option<FrameId> {
+        self.parent
+    }
+
+    pub fn locals(&self) -> &HashMap<LexStr, CellType> {
+        &self.locals
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Frames {
+    frames: Vec<Frame>
+}
+
+impl Frames {
+    pub fn new() -> Self {
+        Frames {
+            frames: Vec::new()
+        }
+    }
+
+    fn push(&mut self, name: LexStr, span: Span, parent: Option<FrameId>) -> FrameId {
+        self.frames.push(Frame {
+            name: name,
+            span: span,
+            parent: parent,
+            locals: HashMap::new()
+        });
+
+        return self.frames.len() - 1;
+    }
+
+    pub fn get(&self, frame: FrameId) -> &Frame {
+        &self.frames[frame]
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // innermost invocation first
+    pub fn calls(&self, frame: Option<FrameId>) -> Vec<MacroCall> {
+        let mut calls = Vec::new();
+        let mut curr = frame;
+
+        while let Some(f) = curr {
+            let f = self.get(f);
+            calls.push(MacroCall { name: f.name.to_string(), span: f.span });
+            curr = f.parent;
+        }
+
+        calls
+    }
+
+    // attaches the invocation chain to errors which don't have one yet
+    pub fn add_backtrace(&self, diag: Diagnostic, frame: Option<FrameId>) -> Diagnostic {
+        if diag.expansion().is_empty() && frame.is_some() {
+            diag.with_expansion(self.calls(frame))
+        }
+        else {
+            diag
+        }
+    }
+}
+
+// Where an instruction came from: its position and the macro invocation it was expanded in
+#[derive(Debug, Clone, Copy)]
+pub struct Origin {
+    pos: LexPos,
+    frame: Option<FrameId>
+}
+
+impl Origin {
+    pub fn new(pos: LexPos, frame: Option<FrameId>) -> Self {
+        Origin {
+            pos: pos,
+            frame: frame
+        }
+    }
+
+    pub fn pos(&self) -> LexPos {
+        self.pos
+    }
+
+    pub fn frame(&self) -> Option<FrameId> {
+        self.frame
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Meta2 {
-    Lab(MetaId, LexPos),
-    Zer(CellType),
-    Inc(CellType),
-    Out(CellType),
-    Mov(CellType, CellType),
-    Jmp(CellType, CellType, MetaId, LexPos)
+    Lab(MetaId, Origin),
+    Zer(CellType, Origin),
+    Inc(CellType, Origin),
+    Out(CellType, Origin),
+    Inp(CellType, Origin),
+    Mov(CellType, CellType, Origin),
+    Jmp(CellType, CellType, MetaId, LexPos, Origin) // with the position of the label argument
 }
 
 pub type CodeMeta2 = LinkedList<Meta2>;
 
+pub struct Expansion {
+    pub code: CodeMeta2,
+    pub frames: Frames,
+    pub registers: HashMap<LexStr, CellType> // global register names
+}
+
 #[derive(Debug, Clone)]
 enum Meta2Arg {
     Reg(CellType),
@@ -120,6 +245,10 @@ enum Meta2Arg {
 }
 
 struct Env {
+    frames: Frames,
+    frame: Option<FrameId>,
+    errors: Vec<Diagnostic>,
+
     next_reg: CellType,
     free_regs: LinkedList<CellType>,
     next_param: ParamType,
@@ -140,6 +269,10 @@ impl Env {
         repl_space.push_level();
 
         Env {
+            frames: Frames::new(),
+            frame: None,
+            errors: Vec::new(),
+
             next_reg: 0,
             free_regs: LinkedList::new(),
             next_param: 0,
@@ -174,15 +307,13 @@ impl Env {
             }
         }
 
-        if let Some(r) = self.free_regs.pop_front() {
-            if is_local {
+        // a freed cell keeps the value of the local it held, so only locals reuse them
+        // and globals start from zero or their initial value
+        if is_local {
+            if let Some(r) = self.free_regs.pop_front() {
                 self.reg_space.put(name, r).unwrap();
-                
-            }
-            else {
-                self.reg_space.put_global(name, r).unwrap();
+                return r;
             }
-            return r;
         }
 
         let new_reg = self.next_reg;
@@ -199,11 +330,7 @@ impl Env {
     }
 
     pub fn get_macro(&mut self, name: &LexStr) -> Option<&MacroData> {
-        let Some(res) = self.macro_space.find(name) else {
-            return None
-        };
-
-        Some(res)
+        self.macro_space.find(name)
     }
 
     pub fn push_level(&mut self, macros: HashMap<LexStr, MacroData>, replacements: HashMap<LexStr, Meta2Arg>) {
@@ -213,9 +340,18 @@ impl Env {
     }
 
     pub fn pop_level(&mut self) {
-        for i in self.reg_space.pop_level().iter() {
-            self.free_regs.push_back(*i.1);
+        let locals = self.reg_space.pop_level();
+
+        // freed in order of index, only locals reuse them and hash order would give their cells other indices on every run
+        let mut freed: Vec<CellType> = locals.values().copied().collect();
+        freed.sort();
+        self.free_regs.extend(freed);
+
+        // kept by their names for debuggers, the level is of the current expansion
+        if let Some(frame) = self.frame {
+            self.frames.frames[frame].locals = locals;
         }
+
         self.macro_space.pop_level();
         self.replacements_space.pop_level();
     }
@@ -228,120 +364,138 @@ impl Env {
 const PARAM_GLOBAL: ParamType = 0;
 
 
-fn expected_arg(pos: LexPos) -> String {
-    format!("{} Expected argument", pos.str())
+fn expected_arg(span: Span) -> Diagnostic {
+    Diagnostic::error(ErrorCode::ExpectedArgument, String::from("Expected argument")).with_span(span)
+}
+
+fn expected_register(span: Span) -> Diagnostic {
+    Diagnostic::error(ErrorCode::ExpectedRegister, String::from("Expected register")).with_span(span)
 }
 
-fn expected_register(pos: LexPos) -> String {
-    format!("{} Expected register", pos.str())
+fn expected_label(span: Span) -> Diagnostic {
+    Diagnostic::error(ErrorCode::ExpectedLabel, String::from("Expected label")).with_span(span)
 }
 
-fn expected_label(pos: LexPos) -> String {
-    format!("{} Expected label", pos.str())
+fn process_label(env: &Env, name: LexStr, deep_level: ParamType, pos: LexPos) -> Meta2 {
+    Meta2::Lab(MetaId::new(name, deep_level), Origin::new(pos, env.frame))
 }
 
-fn process_label(name: LexStr, deep_level: ParamType, pos: LexPos) -> Meta2 {
-    Meta2::Lab(MetaId::new(name, deep_level), pos)
+fn cannot_expand(name: &LexStr, span: Span) -> Diagnostic {
+    Diagnostic::error(ErrorCode::CannotExpand, format!("Cannot expand '{}'", name)).with_span(span)
 }
 
-fn arg_to_meta(env: &mut Env, param: ParamType, meta: MetaArg) -> Result<(Meta2Arg, LexPos), String> {
+fn arg_to_meta(env: &mut Env, param: ParamType, meta: MetaArg) -> Result<(Meta2Arg, Span), Diagnostic> {
+    let span = meta.span();
+
     match meta {
-        MetaArg::Reg(name, is_local, nls,  pos) => Ok(( Meta2Arg::Reg(env.get_reg(name, is_local, nls)), pos )),
-        MetaArg::Lab(name, is_local, pos) => Ok(( Meta2Arg::Lab(MetaId::new(name, if is_local { param } else { PARAM_GLOBAL } )), pos )),
-        MetaArg::Id(name, pos) => match env.replace(&name) {
-            None => Err(format!("{} Cannot expand '{}'", pos.str(), name)),
-            Some(val) => Ok((val.clone(), pos)),
+        MetaArg::Reg(name, is_local, nls,  _) => Ok(( Meta2Arg::Reg(env.get_reg(name, is_local, nls)), span )),
+        MetaArg::Lab(name, is_local, _) => Ok(( Meta2Arg::Lab(MetaId::new(name, if is_local { param } else { PARAM_GLOBAL } )), span )),
+        MetaArg::Id(name, _) => match env.replace(&name) {
+            None => Err(cannot_expand(&name, span)),
+            Some(val) => Ok((val.clone(), span)),
         },
-        MetaArg::Code(_, _, pos) => Err(format!("{} Expected non-macro argument", pos.str())),
+        MetaArg::Code(_, _, _) => Err(Diagnostic::error(ErrorCode::ExpectedNonMacroArgument, String::from("Expected non-macro argument")).with_span(span)),
     }
 }
 
-fn process_zer(env: &mut Env, param: ParamType, arg_iter: &mut IntoIter<MetaArg>, pos: LexPos) -> Result<Meta2, String> {
+fn process_zer(env: &mut Env, param: ParamType, arg_iter: &mut IntoIter<MetaArg>, span: Span) -> Result<Meta2, Diagnostic> {
+    let arg1 = match arg_iter.next() {
+        None => return Err(expected_arg(span)),
+        Some(meta) => match arg_to_meta(env, param, meta)? {
+            (Meta2Arg::Reg(v), _) => v,
+            (Meta2Arg::Lab(_), span) => return Err(expected_register(span)),
+        }
+    };
+
+    Ok(Meta2::Zer(arg1, Origin::new(span.pos(), env.frame)))
+}
+
+fn process_inc(env: &mut Env, param: ParamType, arg_iter: &mut IntoIter<MetaArg>, span: Span) -> Result<Meta2, Diagnostic> {
     let arg1 = match arg_iter.next() {
-        None => return Err(expected_arg(pos)),
+        None => return Err(expected_arg(span)),
         Some(meta) => match arg_to_meta(env, param, meta)? {
             (Meta2Arg::Reg(v), _) => v,
-            (Meta2Arg::Lab(_), pos) => return Err(expected_register(pos)),
+            (Meta2Arg::Lab(_), span) => return Err(expected_register(span)),
         }
     };
 
-    Ok(Meta2::Zer(arg1))
+    Ok(Meta2::Inc(arg1, Origin::new(span.pos(), env.frame)))
 }
 
-fn process_inc(env: &mut Env, param: ParamType, arg_iter: &mut IntoIter<MetaArg>, pos: LexPos) -> Result<Meta2, String> {
+fn process_out(env: &mut Env, param: ParamType, arg_iter: &mut IntoIter<MetaArg>, span: Span) -> Result<Meta2, Diagnostic> {
     let arg1 = match arg_iter.next() {
-        None => return Err(expected_arg(pos)),
+        None => return Err(expected_arg(span)),
         Some(meta) => match arg_to_meta(env, param, meta)? {
             (Meta2Arg::Reg(v), _) => v,
-            (Meta2Arg::Lab(_), pos) => return Err(expected_register(pos)),
+            (Meta2Arg::Lab(_), span) => return Err(expected_register(span)),
         }
     };
 
-    Ok(Meta2::Inc(arg1))
+    Ok(Meta2::Out(arg1, Origin::new(span.pos(), env.frame)))
 }
 
-fn process_out(env: &mut Env, param: ParamType, arg_iter: &mut IntoIter<MetaArg>, pos: LexPos) -> Result<Meta2, String> {
+fn process_inp(env: &mut Env, param: ParamType, arg_iter: &mut IntoIter<MetaArg>, span: Span) -> Result<Meta2, Diagnostic> {
     let arg1 = match arg_iter.next() {
-        None => return Err(expected_arg(pos)),
+        None => return Err(expected_arg(span)),
         Some(meta) => match arg_to_meta(env, param, meta)? {
             (Meta2Arg::Reg(v), _) => v,
-            (Meta2Arg::Lab(_), pos) => return Err(expected_register(pos)),
+            (Meta2Arg::Lab(_), span) => return Err(expected_register(span)),
         }
     };
 
-    Ok(Meta2::Out(arg1))
+    Ok(Meta2::Inp(arg1, Origin::new(span.pos(), env.frame)))
 }
 
-fn process_mov(env: &mut Env, param: ParamType, arg_iter: &mut IntoIter<MetaArg>, pos: LexPos) -> Result<Meta2, String> {
+fn process_mov(env: &mut Env, param: ParamType, arg_iter: &mut IntoIter<MetaArg>, span: Span) -> Result<Meta2, Diagnostic> {
     let arg1 = match arg_iter.next() {
-        None => return Err(expected_arg(pos)),
+        None => return Err(expected_arg(span)),
         Some(meta) => match arg_to_meta(env, param, meta)? {
             (Meta2Arg::Reg(v), _) => v,
-            (Meta2Arg::Lab(_), pos) => return Err(expected_register(pos)),
+            (Meta2Arg::Lab(_), span) => return Err(expected_register(span)),
         }
     };
     let arg2 = match arg_iter.next() {
-        None => return Err(expected_arg(pos)),
+        None => return Err(expected_arg(span)),
         Some(meta) => match arg_to_meta(env, param, meta)? {
             (Meta2Arg::Reg(v), _) => v,
-            (Meta2Arg::Lab(_), pos) => return Err(expected_register(pos)),
+            (Meta2Arg::Lab(_), span) => return Err(expected_register(span)),
         }
     };
 
-    Ok(Meta2::Mov(arg1, arg2))
+    Ok(Meta2::Mov(arg1, arg2, Origin::new(span.pos(), env.frame)))
 }
 
-fn process_jmp(env: &mut Env, param: ParamType, arg_iter: &mut IntoIter<MetaArg>, pos: LexPos) -> Result<Meta2, String> {
+fn process_jmp(env: &mut Env, param: ParamType, arg_iter: &mut IntoIter<MetaArg>, span: Span) -> Result<Meta2, Diagnostic> {
     let arg1 = match arg_iter.next() {
-        None => return Err(expected_arg(pos)),
+        None => return Err(expected_arg(span)),
         Some(meta) => match arg_to_meta(env, param, meta)? {
             (Meta2Arg::Reg(v), _) => v,
-            (Meta2Arg::Lab(_), pos) => return Err(expected_register(pos)),
+            (Meta2Arg::Lab(_), span) => return Err(expected_register(span)),
         }
     };
     let arg2 = match arg_iter.next() {
-        None => return Err(expected_arg(pos)),
+        None => return Err(expected_arg(span)),
         Some(meta) => match arg_to_meta(env, param, meta)? {
             (Meta2Arg::Reg(v), _) => v,
-            (Meta2Arg::Lab(_), pos) => return Err(expected_register(pos)),
+            (Meta2Arg::Lab(_), span) => return Err(expected_register(span)),
         }
     };
-    let arg3 = match arg_iter.next() {
-        None => return Err(expected_arg(pos)),
+    let (arg3, label_pos) = match arg_iter.next() {
+        None => return Err(expected_arg(span)),
         Some(meta) => match arg_to_meta(env, param, meta)? {
-            (Meta2Arg::Reg(_), pos) => return Err(expected_label(pos)),
-            (Meta2Arg::Lab(v), _) => v,
+            (Meta2Arg::Reg(_), span) => return Err(expected_label(span)),
+            (Meta2Arg::Lab(v), span) => (v, span.pos()),
         }
     };
 
-    Ok(Meta2::Jmp(arg1, arg2, arg3, pos))
+    Ok(Meta2::Jmp(arg1, arg2, arg3, label_pos, Origin::new(span.pos(), env.frame)))
 }
 
-fn expand_macro(env: &mut Env, param: ParamType, name: LexStr, args: &mut IntoIter<MetaArg>, pos: LexPos) -> Result<CodeMeta2, String> {
+fn expand_macro(env: &mut Env, param: ParamType, name: LexStr, args: &mut IntoIter<MetaArg>, span: Span) -> Result<CodeMeta2, Diagnostic> {
     let inner_param = env.next_param();
 
     let Some((ids, code)) = env.get_macro(&name).cloned() else {
-        return Err(format!("{} Cannot find macro '{}'", pos.str(), name));
+        return Err(Diagnostic::error(ErrorCode::MacroNotFound, format!("Cannot find macro '{}'", name)).with_span(span));
     };
 
     let mut macr = HashMap::new();
@@ -349,7 +503,7 @@ fn expand_macro(env: &mut Env, param: ParamType, name: LexStr, args: &mut IntoIt
 
     for i in ids.iter() {
         match args.next() {
-            None => return Err(expected_arg(pos)),
+            None => return Err(expected_arg(span)),
             Some(arg) => match arg {
                 MetaArg::Reg(name, is_local, nls, _) => {
                     reps.insert(Rc::clone(i), Meta2Arg::Reg( env.get_reg(name, is_local, nls) ));
@@ -366,78 +520,99 @@ fn expand_macro(env: &mut Env, param: ParamType, name: LexStr, args: &mut IntoIt
                         Some(data) => { 
                             macr.insert(Rc::clone(i), data.clone()); 
                         },
-                        None => return Err(format!("{} Cannot expand '{}'", pos.str(), name)),
+                        None => return Err(cannot_expand(&name, Span::new(pos, name.chars().count() as LexPosType))),
                     },
                 },
             },
         }
     }
 
+    let frame = env.frames.push(name, span, env.frame);
+    let outer_frame = env.frame.replace(frame);
     env.push_level(macr, reps);
 
+    let to_ret = expand_body(env, inner_param, code);
+
+    env.pop_level();
+    env.frame = outer_frame;
+
+    Ok(to_ret)
+}
+
+// Broken operations are reported to env and skipped, so one expansion can report several errors
+fn expand_body(env: &mut Env, param: ParamType, code: CodeMeta) -> CodeMeta2 {
     let mut to_ret = CodeMeta2::new();
 
     for i in code.into_iter() {
         match i {
-            Meta::Op(name, op_args, op_pos) => to_ret.append(&mut process_op(env, inner_param, name, op_args, op_pos)?),
-            Meta::Lab(name, is_local, pos) => to_ret.push_back(Meta2::Lab(MetaId::new(name, if is_local { inner_param } else { PARAM_GLOBAL } ), pos))
+            Meta::Op(name, op_args, op_pos) => match process_op(env, param, name, op_args, op_pos) {
+                Ok(mut code) => to_ret.append(&mut code),
+                Err(diag) => {
+                    let diag = env.frames.add_backtrace(diag, env.frame);
+                    env.errors.push(diag);
+                },
+            },
+            Meta::Lab(name, is_local, pos) => to_ret.push_back(process_label(env, name, if is_local { param } else { PARAM_GLOBAL }, pos))
         }
     }
 
-    env.pop_level();
-
-    Ok(to_ret)
+    to_ret
 }
 
-fn process_op(env: &mut Env, param: ParamType, name: LexStr, args: MetaArgs, pos: LexPos) -> Result<CodeMeta2, String> {
+fn process_op(env: &mut Env, param: ParamType, name: LexStr, args: MetaArgs, pos: LexPos) -> Result<CodeMeta2, Diagnostic> {
     let mut to_ret = CodeMeta2::new();
     let mut iter = args.into_iter();
+    let span = Span::new(pos, name.chars().count() as LexPosType);
     
     match name.as_str() {
-        "zer" => to_ret.push_back(process_zer(env, param, &mut iter, pos)?),
-        "inc" => to_ret.push_back(process_inc(env, param, &mut iter, pos)?),
-        "out" => to_ret.push_back(process_out(env, param, &mut iter, pos)?),
+        "zer" => to_ret.push_back(process_zer(env, param, &mut iter, span)?),
+        "inc" => to_ret.push_back(process_inc(env, param, &mut iter, span)?),
+        "out" => to_ret.push_back(process_out(env, param, &mut iter, span)?),
+        "inp" => to_ret.push_back(process_inp(env, param, &mut iter, span)?),
 
-        "mov" => to_ret.push_back(process_mov(env, param, &mut iter, pos)?),
-        "jmp" => to_ret.push_back(process_jmp(env, param, &mut iter, pos)?),
+        "mov" => to_ret.push_back(process_mov(env, param, &mut iter, span)?),
+        "jmp" => to_ret.push_back(process_jmp(env, param, &mut iter, span)?),
 
-        _ => to_ret.append(&mut expand_macro(env, param, name, &mut iter, pos)?),
+        _ => to_ret.append(&mut expand_macro(env, param, name, &mut iter, span)?),
     }
 
     if let Some(arg) = iter.next() {
-        return Err(format!("{} Extra argument: {}", arg.pos_str(), arg.str()))
+        return Err(Diagnostic::error(ErrorCode::ExtraArgument, format!("Extra argument: {}", arg.str())).with_span(arg.span()))
     }
 
     Ok(to_ret)
 }
 
-pub fn to_meta2(meta: CodeMeta, macros: HashMap<LexStr, MacroData>) -> Result<CodeMeta2, String> {
+// `inputs` are global registers allocated before anything else, so they never share
+// a cell with a local register and may be given initial values.
+// Broken operations are left out of the expansion and their errors are added to `errors`.
+pub fn to_meta2(meta: CodeMeta, macros: HashMap<LexStr, MacroData>, inputs: &[String], errors: &mut Vec<Diagnostic>) -> Expansion {
     let mut env = Env::new(macros);
-    let mut meta2 = CodeMeta2::new();
 
-    for i in meta.into_iter() {
-        match i {
-            Meta::Op(name, args, pos) => meta2.append(
-                &mut process_op(&mut env, 0, name, args, pos)?
-            ),
-            Meta::Lab(name, _, pos) => meta2.push_back(
-                process_label(name, PARAM_GLOBAL, pos)
-            ),
-        }
+    for name in inputs.iter() {
+        env.get_reg(Rc::new(name.clone()), false, false);
     }
 
-    Ok(meta2)
+    let meta2 = expand_body(&mut env, PARAM_GLOBAL, meta);
+    errors.append(&mut env.errors);
+
+    Expansion {
+        code: meta2,
+        registers: env.reg_space.global().clone(),
+        frames: env.frames
+    }
 }
 
 pub fn print_meta2(meta: &CodeMeta2) {
     for i in meta.iter() {
         match i {
             Meta2::Lab(id, _) => println!("@{}_{}", id.id(), id.param()),
-            Meta2::Zer(r) => println!("    zer %{}", *r),
-            Meta2::Inc(r) => println!("    inc %{}", *r),
-            Meta2::Out(r) => println!("    out %{}", *r),
-            Meta2::Mov(r1, r2) => println!("    mov %{} %{}", *r1, *r2),
-            Meta2::Jmp(r1, r2, l, _) => println!("    jmp %{} %{} @{}_{}", *r1, *r2, l.id(), l.param()),
+            Meta2::Zer(r, _) => println!("    zer %{}", *r),
+            Meta2::Inc(r, _) => println!("    inc %{}", *r),
+            Meta2::Out(r, _) => println!("    out %{}", *r),
+            Meta2::Inp(r, _) => println!("    inp %{}", *r),
+            Meta2::Mov(r1, r2, _) => println!("    mov %{} %{}", *r1, *r2),
+            Meta2::Jmp(r1, r2, l, _, _) => println!("    jmp %{} %{} @{}_{}", *r1, *r2, l.id(), l.param()),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,585 @@
+use crate::source_map::SourceMap;
+use crate::vm::{CellType, Env, Op, PosType};
+
+// MUR has no arithmetic, so the standard macros count: `sum` takes a step per unit of the number
+// it adds. `fuse_loops` finds the loops of these macros and marks their first instructions,
+// the machine then does a marked loop at once. The code stays as it was, jumps into a loop
+// and the source map keep working, and when a loop can't be done exactly at once (it would
+// never end, a register is too big, the step limit comes inside it) the machine executes
+// the first instruction of the loop as usual.
+//
+// Fused loops don't report the steps inside them to observers, so traced, profiled
+// and debugged programs run the code as written.
+
+// A loop done at once by the machine, it exists only on top of the instructions it was found in
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Loop {
+    Add(CellType, CellType, CellType),    // x, i, y: `while i != y { inc x; inc i }`
+    CountTo(CellType, CellType),          // x, y: `while x != y { inc x }`
+    Dec(CellType, CellType, CellType),    // x, one, res: `x = x - one` by counting `res` up
+    MulAdd(CellType, CellType, CellType), // acc, u, y: `while u != y { acc += x - z; inc u }`
+    SubSat(CellType, CellType, CellType), // x, y, zero: `x -= 1; y -= 1` until one of them is zero
+}
+
+// Code with the loops found in it, made by `fuse_loops` and run by `vm::execute_fused`
+pub struct FusedCode {
+    code: Vec<Op>,
+    loops: Vec<Option<Loop>> // by the first instruction of the loop
+}
+
+impl FusedCode {
+    pub fn code(&self) -> &[Op] {
+        &self.code
+    }
+
+    // number of loops found
+    pub fn fused(&self) -> usize {
+        self.loops.iter().filter(|l| l.is_some()).count()
+    }
+
+    pub(crate) fn loops(&self) -> &[Option<Loop>] {
+        &self.loops
+    }
+}
+
+fn distinct(regs: &[CellType]) -> bool {
+    regs.iter().enumerate().all(|(i, r)| !regs[..i].contains(r))
+}
+
+// `jmp %c %c @to`, the macro `jp`
+fn is_goto(op: Option<&Op>, to: PosType) -> bool {
+    matches!(op, Some(Op::Jmp(r1, r2, t)) if r1 == r2 && *t == to)
+}
+
+// @l:  jmp %i %y @exit
+//      inc %x              (the two `inc` in any order)
+//      inc %i
+//      jmp %c %c @l
+// @exit:
+fn match_add(code: &[Op], l: PosType) -> Option<Loop> {
+    let Some(Op::Jmp(i, y, exit)) = code.get(l).copied() else {
+        return None;
+    };
+
+    if exit != l + 4 || !is_goto(code.get(l + 3), l) {
+        return None;
+    }
+
+    let x = match (code.get(l + 1).copied()?, code.get(l + 2).copied()?) {
+        (Op::Inc(x), Op::Inc(r)) if r == i => x,
+        (Op::Inc(r), Op::Inc(x)) if r == i => x,
+        _ => return None,
+    };
+
+    distinct(&[x, i, y]).then_some(Loop::Add(x, i, y))
+}
+
+// @l:  jmp %x %y @exit
+//      inc %x
+//      jmp %c %c @l
+// @exit:
+fn match_count_to(code: &[Op], l: PosType) -> Option<Loop> {
+    let Some(Op::Jmp(x, y, exit)) = code.get(l).copied() else {
+        return None;
+    };
+
+    let fits = exit == l + 3 && code.get(l + 1) == Some(&Op::Inc(x)) && is_goto(code.get(l + 2), l);
+
+    (fits && x != y).then_some(Loop::CountTo(x, y))
+}
+
+// The macro `dec`:
+//      zer %res
+//      mov %u %one
+//      (add loop of %res, %u, %x)
+//      mov %x %res
+fn match_dec(code: &[Op], h: PosType) -> Option<Loop> {
+    let (Some(Op::Zero(res)), Some(Op::Mov(u, one))) = (code.get(h).copied(), code.get(h + 1).copied()) else {
+        return None;
+    };
+
+    let Some(Loop::Add(r, i, x)) = match_add(code, h + 2) else {
+        return None;
+    };
+
+    let fits = r == res && i == u && code.get(h + 6) == Some(&Op::Mov(x, res));
+
+    (fits && distinct(&[x, one, res, u])).then_some(Loop::Dec(x, one, res))
+}
+
+// The macro `rep` around `sum`, as in `mul`:
+// @l:  jmp %u %y @exit
+//      mov %v %z
+//      (add loop of %acc, %v, %x)
+//      inc %u
+//      jmp %c %c @l
+// @exit:
+fn match_mul_add(code: &[Op], l: PosType) -> Option<Loop> {
+    let (Some(Op::Jmp(u, y, exit)), Some(Op::Mov(v, z))) = (code.get(l).copied(), code.get(l + 1).copied()) else {
+        return None;
+    };
+
+    let Some(Loop::Add(acc, i, x)) = match_add(code, l + 2) else {
+        return None;
+    };
+
+    let fits = exit == l + 8 && i == v && code.get(l + 6) == Some(&Op::Inc(u)) && is_goto(code.get(l + 7), l);
+
+    (fits && distinct(&[acc, u, y, v, z, x])).then_some(Loop::MulAdd(acc, u, y))
+}
+
+// The macro `sub`:
+// @l:  jmp %y %zero @exit
+//      jmp %x %zero @fail
+//      (dec of %x)
+//      (dec of %y)
+//      jmp %c %c @l
+// @exit:
+fn match_sub_sat(code: &[Op], l: PosType) -> Option<Loop> {
+    let (Some(Op::Jmp(y, zero, exit)), Some(Op::Jmp(x, z, _))) = (code.get(l).copied(), code.get(l + 1).copied()) else {
+        return None;
+    };
+
+    let (Some(Loop::Dec(dx, one, res1)), Some(Loop::Dec(dy, one2, res2))) = (match_dec(code, l + 2), match_dec(code, l + 9)) else {
+        return None;
+    };
+
+    let (Some(Op::Mov(u1, _)), Some(Op::Mov(u2, _))) = (code.get(l + 3).copied(), code.get(l + 10).copied()) else {
+        return None;
+    };
+
+    let fits = exit == l + 17 && z == zero && dx == x && dy == y && one2 == one && is_goto(code.get(l + 16), l);
+
+    // the registers of each `dec` may be shared with the other one, but not with the numbers
+    let apart = distinct(&[x, y, zero, one])
+        && ![res1, u1].iter().any(|r| *r == y || *r == zero)
+        && ![res2, u2].iter().any(|r| *r == x || *r == zero);
+
+    (fits && apart).then_some(Loop::SubSat(x, y, zero))
+}
+
+// Finds every known loop in the code
+pub fn fuse_loops(code: &[Op]) -> FusedCode {
+    let loops = (0..code.len())
+        .map(|l| match_add(code, l)
+            .or_else(|| match_count_to(code, l))
+            .or_else(|| match_dec(code, l))
+            .or_else(|| match_mul_add(code, l))
+            .or_else(|| match_sub_sat(code, l)))
+        .collect();
+
+    FusedCode {
+        code: code.to_vec(),
+        loops: loops
+    }
+}
+
+// Does the whole loop starting at `ip` if it takes at most `budget` steps,
+// returns where the loop exits and how many steps it took
+pub(crate) fn run_loop(l: Loop, code: &[Op], ip: PosType, env: &mut Env, budget: u64) -> Option<(PosType, u64)> {
+    let within = |steps: u128| (steps <= budget as u128).then_some(steps as u64);
+
+    match l {
+        Loop::Add(x, i, y) => {
+            let (xv, iv, yv) = (env.small(x)? as u128, env.small(i)? as u128, env.small(y)? as u128);
+            if iv > yv {
+                return None;
+            }
+
+            let d = yv - iv;
+            let steps = within(4 * d + 1)?;
+
+            // a loop which isn't entered writes nothing
+            if d > 0 {
+                env.set(x, xv + d);
+                env.set(i, yv);
+            }
+
+            Some((ip + 4, steps))
+        },
+        Loop::CountTo(x, y) => {
+            let (xv, yv) = (env.small(x)? as u128, env.small(y)? as u128);
+            if xv > yv {
+                return None;
+            }
+
+            let steps = within(3 * (yv - xv) + 1)?;
+            if xv < yv {
+                env.set(x, yv);
+            }
+
+            Some((ip + 3, steps))
+        },
+        Loop::Dec(x, one, res) => {
+            let Op::Mov(u, _) = code[ip + 1] else {
+                return None;
+            };
+
+            let (xv, ov) = (env.small(x)? as u128, env.small(one)? as u128);
+            if ov > xv {
+                return None;
+            }
+
+            let d = xv - ov;
+            let steps = within(4 * d + 4)?;
+
+            env.set(res, d);
+            env.set(u, xv);
+            env.set(x, d);
+
+            Some((ip + 7, steps))
+        },
+        Loop::MulAdd(acc, u, y) => {
+            let (Op::Mov(v, z), Op::Jmp(_, x, _)) = (code[ip + 1], code[ip + 2]) else {
+                return None;
+            };
+
+            let (uv, yv) = (env.small(u)? as u128, env.small(y)? as u128);
+            if uv > yv {
+                return None;
+            }
+            if uv == yv {
+                return Some((ip + 8, within(1)?));
+            }
+
+            let (accv, zv, xv) = (env.small(acc)? as u128, env.small(z)? as u128, env.small(x)? as u128);
+            if zv > xv {
+                return None;
+            }
+
+            // every iteration adds `x - z` in 4 (x - z) + 5 steps
+            let (d, e) = (yv - uv, xv - zv);
+            let steps = within(d.checked_mul(4 * e + 5)? + 1)?;
+
+            // adding zero leaves the sum unwritten, as the inner loop is never entered
+            if e > 0 {
+                env.set(acc, accv.checked_add(d * e)?);
+            }
+            env.set(v, xv);
+            env.set(u, yv);
+
+            Some((ip + 8, steps))
+        },
+        Loop::SubSat(x, y, zero) => {
+            let (Op::Jmp(_, _, fail), Op::Zero(res1), Op::Mov(u1, one), Op::Zero(res2), Op::Mov(u2, _))
+                = (code[ip + 1], code[ip + 2], code[ip + 3], code[ip + 9], code[ip + 10]) else {
+                return None;
+            };
+
+            // other values would make the loop count by other amounts or never end
+            if env.small(zero)? != 0 || env.small(one)? != 1 {
+                return None;
+            }
+
+            let (xv, yv) = (env.small(x)? as u128, env.small(y)? as u128);
+            if yv == 0 {
+                return Some((ip + 17, within(1)?));
+            }
+            if xv == 0 {
+                return Some((fail, within(2)?));
+            }
+
+            // the iteration `j` takes 3 + 4 (x - j) + 4 (y - j) steps, the loop ends when one of them is zero
+            let k = xv.min(yv);
+            let (next, exit_steps) = if yv <= xv { (ip + 17, 1) } else { (fail, 2) };
+            let steps = within(k.checked_mul(4 * xv + 4 * yv + 7 - 4 * k)? + exit_steps)?;
+
+            env.set(res1, xv - k);
+            env.set(u1, xv - k + 1);
+            env.set(x, xv - k);
+            env.set(res2, yv - k);
+            env.set(u2, yv - k + 1);
+            env.set(y, yv - k);
+
+            Some((next, steps))
+        },
+    }
+}
+
+// the target of a jump which goes on through unconditional jumps, a loop of them is left alone
+fn thread(code: &[Op], to: PosType) -> PosType {
+    let mut next = to;
+
+    for _ in 0..code.len() {
+        match code.get(next) {
+            Some(Op::Jmp(r1, r2, t)) if r1 == r2 => next = *t,
+            _ => return next,
+        }
+    }
+
+    to
+}
+
+// instructions which change nothing the next one doesn't overwrite
+fn is_noop(code: &[Op], ip: PosType) -> bool {
+    match (code[ip], code.get(ip + 1)) {
+        (Op::Mov(a, b), _) => a == b,
+        // going on to the same place whether the registers are equal or not, the target
+        // is already threaded, so the next instruction is followed the same way
+        (Op::Jmp(_, _, to), _) => thread(code, to) == thread(code, ip + 1),
+        (Op::Zero(a), Some(Op::Mov(b, c))) => a == *b && b != c,
+        _ => false,
+    }
+}
+
+// Threads jumps through unconditional jumps and removes instructions which do nothing,
+// until neither finds anything more. Returns how many instructions were removed
+pub fn peephole(code: &mut Vec<Op>, source_map: &mut SourceMap) -> usize {
+    let len = code.len();
+
+    loop {
+        let mut threaded = false;
+
+        for ip in 0..code.len() {
+            if let Op::Jmp(r1, r2, to) = code[ip] {
+                let to2 = thread(code, to);
+
+                if to2 != to {
+                    code[ip] = Op::Jmp(r1, r2, to2);
+                    threaded = true;
+                }
+            }
+        }
+
+        let keep: Vec<bool> = (0..code.len()).map(|ip| !is_noop(code, ip)).collect();
+
+        if !threaded && keep.iter().all(|k| *k) {
+            break;
+        }
+
+        // the new place of every instruction and of the end, a removed one goes to the next kept
+        let mut new_ip = Vec::with_capacity(code.len() + 1);
+        let mut kept = 0;
+
+        for k in keep.iter() {
+            new_ip.push(kept);
+            kept += *k as PosType;
+        }
+        new_ip.push(kept);
+
+        *code = code.iter()
+            .zip(keep.iter())
+            .filter(|(_, keep)| **keep)
+            .map(|(op, _)| match *op {
+                Op::Jmp(r1, r2, to) => Op::Jmp(r1, r2, new_ip[to]),
+                op => op,
+            })
+            .collect();
+
+        source_map.remap(&keep, &new_ip);
+    }
+
+    len - code.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use super::*;
+    use crate::bignat::BigNat;
+    use crate::parser::{Parser, Program};
+    use crate::vm::{execute, execute_fused, Input, InputMode, Limits, Output, OutputMode, Report};
+
+    // the macros of examples/println.mur which make the loops
+    const MACROS: &str = "
+        macro jp M { jmp %0 %0 M; }
+
+        macro rep S_rep N_rep what {
+            mov %.u S_rep
+            @.rep_loop
+                jmp %.u N_rep @.rep_out
+                what
+                inc %.u
+                jp @.rep_loop
+            @.rep_out
+        }
+
+        macro sum X Y {
+            rep %0 Y {| inc X ;}
+        }
+
+        macro mul X Y {
+            zer %.res
+            rep %0 Y {| sum %\\.res X ;}
+            mov X %.res
+        }
+
+        macro dec X {
+            zer %.res
+            rep %1 X {| inc %\\.res ;}
+            mov X %.res
+        }
+
+        macro sub X Y M {
+            mov %.x X
+            mov %.y Y
+            @.sub_loop
+                jmp %.y %0 @.sub_out
+                jmp %.x %0 M
+                dec %.x
+                dec %.y
+                jp @.sub_loop
+            @.sub_out
+                mov X %.x
+        }
+
+        zer %0
+        zer %1
+        inc %1
+    ";
+
+    const PROGRAMS: [(&str, &str); 6] = [
+        ("sum", "sum %x %y"),
+        ("count-to", "@l\njmp %x %y @e\ninc %x\njp @l\n@e"),
+        ("dec", "dec %x\ndec %y"),
+        ("mul", "mul %x %y"),
+        // the sum isn't zeroed first, adding zero times must leave it unwritten
+        ("mul-add", "rep %0 %y {| sum %acc %x ;}"),
+        ("sub", "sub %x %y @fail\ninc %ok\n@fail"),
+    ];
+
+    // a loop which never ends, as counting to a smaller number, stops here
+    const MAX_STEPS: u64 = 5_000;
+
+    fn values() -> Vec<BigNat> {
+        let mut values: Vec<BigNat> = [0u64, 1, 2, 5, 7].iter().map(|v| BigNat::from(*v)).collect();
+
+        // the sum leaves a machine word, the fused loop must not be used for it
+        values.push(BigNat::from(u64::MAX - 1));
+        values
+    }
+
+    fn run(code: &[Op], fused: Option<&FusedCode>, regs: &[BigNat], max_steps: u64) -> Report {
+        let limits = Limits { max_steps: Some(max_steps), ..Limits::default() };
+        let input = Input::new(Cursor::new(Vec::new()), InputMode::Byte);
+        let output = Output::new(io::sink(), OutputMode::Decimal);
+
+        match fused {
+            Some(fused) => execute_fused(fused, regs.to_vec(), &limits, input, output),
+            None => execute(code, regs.to_vec(), &limits, input, output),
+        }
+    }
+
+    fn assert_same(fused: &Report, plain: &Report, what: &str) {
+        assert_eq!(fused.halt, plain.halt, "{}: halt", what);
+        assert_eq!(fused.ip, plain.ip, "{}: ip", what);
+        assert_eq!(fused.steps, plain.steps, "{}: steps", what);
+        assert_eq!(fused.regs, plain.regs, "{}: registers", what);
+        assert_eq!(fused.regs_touched, plain.regs_touched, "{}: touched registers", what);
+    }
+
+    #[test]
+    fn fused_loops_behave_as_written() {
+        for (name, body) in PROGRAMS.iter() {
+            let mut parser = Parser::from_str(&format!("{}\n{}\n", MACROS, body));
+            parser.set_inputs(vec![String::from("x"), String::from("y")]);
+            let program = parser.parse().unwrap_or_else(|_| panic!("{} doesn't parse", name));
+
+            let fused = fuse_loops(&program.code);
+            assert!(fused.fused() > 0, "{}: no loop was found", name);
+            assert_eq!(fused.code(), program.code.as_slice());
+
+            let (x, y) = (program.register("x").unwrap(), program.register("y").unwrap());
+            let size = program.code.iter().flat_map(|op| op.regs()).max().unwrap() + 1;
+
+            for xv in values() {
+                for yv in values().iter().take(5) {
+                    let mut regs = vec![BigNat::new(); size];
+                    regs[x] = xv.clone();
+                    regs[y] = yv.clone();
+
+                    let plain = run(&program.code, None, &regs, MAX_STEPS);
+                    assert_same(&run(&program.code, Some(&fused), &regs, MAX_STEPS), &plain, &format!("{} x={} y={}", name, xv, yv));
+
+                    // step limits inside the loops, all of them in short runs
+                    for max_steps in (0..=plain.steps).filter(|m| *m < 300 || m % 97 == 0 || *m == plain.steps) {
+                        let what = format!("{} x={} y={} max_steps={}", name, xv, yv, max_steps);
+                        assert_same(&run(&program.code, Some(&fused), &regs, max_steps), &run(&program.code, None, &regs, max_steps), &what);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn loops_match_only_whole() {
+        // the `sum` loop without its jump back
+        let code = [Op::Jmp(0, 1, 4), Op::Inc(2), Op::Inc(0)];
+        assert_eq!(fuse_loops(&code).fused(), 0);
+
+        let code = [Op::Jmp(0, 1, 4), Op::Inc(2), Op::Inc(0), Op::Jmp(3, 3, 0)];
+        assert_eq!(fuse_loops(&code).loops(), &[Some(Loop::Add(2, 0, 1)), None, None, None]);
+    }
+
+    // registers are numbered in order of appearance: %a is 0, %b is 1, %c is 2
+    fn optimized(source: &str) -> (Program, usize) {
+        let mut parser = Parser::from_str(source);
+        parser.set_optimize(true);
+
+        let program = parser.parse().unwrap_or_else(|_| panic!("{:?} doesn't parse", source));
+        (program, parser.removed())
+    }
+
+    fn lines(program: &Program) -> Vec<usize> {
+        (0..program.code.len()).map(|ip| program.source_map.pos(ip).unwrap().line() as usize).collect()
+    }
+
+    #[test]
+    fn noops_are_removed() {
+        let (program, removed) = optimized("inc %a\nmov %a %a\nout %a\n");
+        assert_eq!((program.code, removed), (vec![Op::Inc(0), Op::Out(0)], 1));
+
+        let (program, removed) = optimized("jmp %a %b @next\n@next\ninc %a\n");
+        assert_eq!((program.code, removed), (vec![Op::Inc(0)], 1));
+
+        let (program, removed) = optimized("zer %a\nmov %a %b\nout %a\n");
+        assert_eq!((program.code, removed), (vec![Op::Mov(0, 1), Op::Out(0)], 1));
+
+        // the move does nothing, so the zero isn't overwritten
+        let (program, removed) = optimized("zer %a\nmov %a %a\nout %a\n");
+        assert_eq!((program.code, removed), (vec![Op::Zero(0), Op::Out(0)], 1));
+    }
+
+    #[test]
+    fn jump_chains_are_threaded() {
+        let (program, removed) = optimized("jmp %a %b @one\ninc %a\n@one\njmp %c %c @two\ninc %b\n@two\nout %a\n");
+        assert_eq!(removed, 0);
+        assert_eq!(program.code[0], Op::Jmp(0, 1, 4));
+
+        // going to the next instruction through an unconditional jump is still a noop
+        let (program, removed) = optimized("jmp %a %b @next\n@next\njmp %c %c @end\ninc %a\n@end\nout %a\n");
+        assert_eq!((program.code, removed), (vec![Op::Jmp(2, 2, 2), Op::Inc(0), Op::Out(0)], 1));
+    }
+
+    #[test]
+    fn jump_cycles_are_kept() {
+        let (program, removed) = optimized("@l\njmp %c %c @l\n");
+        assert_eq!((program.code, removed), (vec![Op::Jmp(0, 0, 0)], 0));
+
+        let (program, removed) = optimized("@l\njmp %c %c @m\n@m\njmp %c %c @l\n");
+        assert_eq!((program.code, removed), (vec![Op::Jmp(0, 0, 0)], 1));
+    }
+
+    #[test]
+    fn jumps_to_the_end_and_to_removed_instructions() {
+        let (program, removed) = optimized("jmp %a %b @end\ninc %a\nmov %a %a\n@end\n");
+        assert_eq!((program.code, removed), (vec![Op::Jmp(0, 1, 2), Op::Inc(0)], 1));
+
+        let (program, removed) = optimized("jmp %a %b @m\ninc %a\n@m\nmov %a %a\nout %a\n");
+        assert_eq!((program.code, removed), (vec![Op::Jmp(0, 1, 2), Op::Inc(0), Op::Out(0)], 1));
+    }
+
+    #[test]
+    fn source_map_follows_the_code() {
+        let (program, _) = optimized("inc %a\nmov %a %a\n@l\nout %a\n@m\nmov %b %b\njmp %a %b @l\njmp %a %b @m\n");
+
+        assert_eq!(program.code, vec![Op::Inc(0), Op::Out(0), Op::Jmp(0, 1, 1), Op::Jmp(0, 1, 2)]);
+        assert_eq!(program.source_map.len(), program.code.len());
+        assert_eq!(lines(&program), vec![1, 4, 7, 8]);
+
+        // the label of a removed instruction goes to the next kept one
+        assert_eq!(program.source_map.label(1), Some("l"));
+        assert_eq!(program.source_map.label(2), Some("m"));
+    }
+}
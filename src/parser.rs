@@ -1,22 +1,88 @@
-use std::collections::{HashMap, LinkedList};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
 
-use crate::{lexer::Lexer, meta::parse_to_meta, meta2::{print_meta2, to_meta2, CodeMeta2, Meta2}, vm::{Op, PosType}};
+use crate::{diagnostic::{Diagnostic, ErrorCode, Span}, lexer::{LexPos, LexPosType, LexStr, Lexer}, meta::{parse_to_meta, Pragmas}, meta2::{print_meta2, to_meta2, Expansion, Meta2, MetaId}, optimize, source::Sources, source_map::SourceMap, vm::{CellType, Op, PosType}};
 
 
+pub struct Program {
+    pub code: Vec<Op>,
+    pub source_map: SourceMap,
+    pub registers: HashMap<LexStr, CellType>, // global register names
+    pub pragmas: Pragmas,
+    used: HashSet<CellType> // registers of the code as written, the optimizer may remove some of their uses
+}
+
+impl Program {
+    // register by its global name, or by raw index written as `#3`
+    pub fn register(&self, name: &str) -> Option<CellType> {
+        if let Some(index) = name.strip_prefix('#') {
+            return index.parse().ok();
+        }
+
+        self.registers.get(&String::from(name)).copied()
+    }
+
+    // also a local `.x` of the macro expansions the instruction is in, the innermost one first
+    pub fn register_at(&self, name: &str, ip: PosType) -> Option<CellType> {
+        match name.strip_prefix('.') {
+            Some(local) => self.source_map.locals(ip).into_iter().flatten().find(|(n, _)| n == local).map(|(_, r)| r),
+            None => self.register(name),
+        }
+    }
+
+    pub fn uses_register(&self, r: CellType) -> bool {
+        self.used.contains(&r)
+    }
+}
+
+pub const DEFAULT_MAX_ERRORS: usize = 100;
+
 pub struct Parser {
     lexer: Lexer,
+    sources: Sources,
+    max_errors: usize,
+    inputs: Vec<String>,
+    optimize: bool,
+    removed: usize, // instructions removed by the peephole pass of the last `parse`
 }
 
 
-fn meta2_to_vm(meta2: LinkedList<Meta2>) -> Result<Vec<Op>, String> {
+fn label_str(l: &MetaId) -> String {
+    format!("{}{}", if l.param() != 0 { "." } else { "" }, l.id())
+}
+
+// unique name of the label in the final code, local labels get the number of their expansion
+fn label_name(l: &MetaId) -> String {
+    if l.param() != 0 {
+        format!("{}_{}", l.id(), l.param())
+    }
+    else {
+        l.id().to_string()
+    }
+}
+
+fn label_span(l: &MetaId, pos: LexPos) -> Span {
+    Span::new(pos, label_str(l).chars().count() as LexPosType + 1)
+}
+
+// jumps to missing labels are left out of the code and their errors are added to `errors`
+fn meta2_to_vm(expansion: Expansion, pragmas: Pragmas, errors: &mut Vec<Diagnostic>) -> Program {
+    let Expansion { code: meta2, frames, registers } = expansion;
+
     let mut labels_pos = HashMap::new();
     let mut op_pointer: PosType = 0;
 
     for i in meta2.iter() {
         match i {
-            Meta2::Lab(name, pos) => {
-                if let Some(_) = labels_pos.insert(name, op_pointer) {
-                    return Err(format!("{} Label '{}{}' is already defined", pos.str(), if name.param() != 0 { "." } else { "" }, name.id()))
+            Meta2::Lab(name, origin) => {
+                if let Some((_, first)) = labels_pos.insert(name, (op_pointer, *origin)) {
+                    let diag = Diagnostic::error(ErrorCode::DuplicateLabel, format!("Label '{}' is already defined", label_str(name)))
+                        .with_span(label_span(name, origin.pos()))
+                        .with_label(label_span(name, first.pos()), String::from("first defined here"));
+
+                    errors.push(frames.add_backtrace(diag, origin.frame()));
                 }
             },
             _ => op_pointer += 1,
@@ -24,47 +90,269 @@ fn meta2_to_vm(meta2: LinkedList<Meta2>) -> Result<Vec<Op>, String> {
     }
 
     let mut vec = Vec::with_capacity(op_pointer);
+    let mut origins = Vec::with_capacity(op_pointer);
 
     for i in meta2.iter() {
         match i {
             Meta2::Lab(_, _) => (),
-            Meta2::Zer(r) => vec.push(Op::Zero(*r)),
-            Meta2::Inc(r) => vec.push(Op::Inc(*r)),
-            Meta2::Out(r) => vec.push(Op::Out(*r)),
-            Meta2::Mov(r1, r2) => vec.push(Op::Mov(*r1, *r2)),
-            Meta2::Jmp(r1, r2, l, pos) => {
-                let Some(v) = labels_pos.get(&l) else {
-                    return Err(format!("{} Label '{}{}' not found", pos.str(), if l.param() != 0 { "." } else { "" }, l.id()))
+            Meta2::Zer(r, origin) => {
+                vec.push(Op::Zero(*r));
+                origins.push(*origin);
+            },
+            Meta2::Inc(r, origin) => {
+                vec.push(Op::Inc(*r));
+                origins.push(*origin);
+            },
+            Meta2::Out(r, origin) => {
+                vec.push(Op::Out(*r));
+                origins.push(*origin);
+            },
+            Meta2::Inp(r, origin) => {
+                vec.push(Op::Inp(*r));
+                origins.push(*origin);
+            },
+            Meta2::Mov(r1, r2, origin) => {
+                vec.push(Op::Mov(*r1, *r2));
+                origins.push(*origin);
+            },
+            Meta2::Jmp(r1, r2, l, label_pos, origin) => {
+                let Some((v, _)) = labels_pos.get(&l) else {
+                    let diag = Diagnostic::error(ErrorCode::LabelNotFound, format!("Label '{}' not found", label_str(l)))
+                        .with_span(label_span(l, *label_pos));
+
+                    errors.push(frames.add_backtrace(diag, origin.frame()));
+                    continue;
                 };
 
                 vec.push(Op::Jmp(*r1, *r2, *v));
+                origins.push(*origin);
             },
         }
     }
 
-    return Ok(vec);
+    let mut labels = HashMap::new();
+    for (l, (v, _)) in labels_pos.iter() {
+        let name = label_name(l);
+
+        // several labels may stand before one instruction, the choice mustn't depend on hash order
+        match labels.get(v) {
+            Some(old) if *old <= name => (),
+            _ => { labels.insert(*v, name); },
+        }
+    }
+
+    let used = vec.iter().flat_map(|op| op.regs()).collect();
+
+    return Program {
+        code: vec,
+        source_map: SourceMap::new(origins, frames, labels),
+        registers: registers,
+        pragmas: pragmas,
+        used: used
+    };
 }
 
 
 impl Parser {
     pub fn new(lexer: Lexer) -> Self {
+        let mut sources = Sources::new();
+        sources.add(lexer.file_name(), None);
+
         Parser {
             lexer: lexer,
+            sources: sources,
+            max_errors: DEFAULT_MAX_ERRORS,
+            inputs: Vec::new(),
+            optimize: false,
+            removed: 0,
         }
     }
 
-    pub fn parse_meta2(&mut self) -> Result<CodeMeta2, String> {
-        let meta_res = parse_to_meta(&mut self.lexer)?;
-        Ok(to_meta2(meta_res.code, meta_res.macros)?)
+    pub fn open(path: &Path) -> Result<Self, Diagnostic> {
+        let (Ok(full_path), Ok(file)) = (fs::canonicalize(path), File::open(path)) else {
+            return Err(Diagnostic::error(ErrorCode::CannotOpenFile, String::from("Cannot open file")));
+        };
+
+        let name = path.to_string_lossy();
+        let mut sources = Sources::new();
+        let id = sources.add(&name, Some(full_path));
+
+        Ok(Parser {
+            lexer: Lexer::with_file(BufReader::new(file), id, &name),
+            sources: sources,
+            max_errors: DEFAULT_MAX_ERRORS,
+            inputs: Vec::new(),
+            optimize: false,
+            removed: 0,
+        })
+    }
+
+    pub fn from_str(source: &str) -> Self {
+        Parser::new(Lexer::from_str(source))
+    }
+
+    // at most this many errors are returned, the rest are dropped
+    pub fn set_max_errors(&mut self, max_errors: usize) {
+        self.max_errors = max_errors;
     }
 
-    pub fn print_debug(&mut self) -> Result<(), String> {
-        print_meta2(&self.parse_meta2()?);
+    // global registers which get initial values, they are allocated first
+    pub fn set_inputs(&mut self, names: Vec<String>) {
+        self.inputs = names;
+    }
+
+    // the peephole pass makes programs take fewer steps, so it's off unless asked for
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    pub fn removed(&self) -> usize {
+        self.removed
+    }
+
+    // one error is always kept, an empty list would mean the program is correct
+    fn limit_errors(&self, mut errors: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        errors.truncate(self.max_errors.max(1));
+        errors
+    }
+
+    // whatever could be expanded, the errors of the rest are added to `errors`
+    fn parse_expansion(&mut self, errors: &mut Vec<Diagnostic>) -> (Expansion, Pragmas) {
+        let meta_res = parse_to_meta(&mut self.lexer, &mut self.sources, errors);
+
+        // expansion runs even after syntax errors, so both are reported at once
+        let expansion = to_meta2(meta_res.code, meta_res.macros, &self.inputs, errors);
+        (expansion, meta_res.pragmas)
+    }
+
+    pub fn parse_meta2(&mut self) -> Result<Expansion, Vec<Diagnostic>> {
+        let mut errors = Vec::new();
+        let (expansion, _) = self.parse_expansion(&mut errors);
+
+        if !errors.is_empty() {
+            return Err(self.limit_errors(errors));
+        }
+
+        Ok(expansion)
+    }
+
+    pub fn print_debug(&mut self) -> Result<(), Vec<Diagnostic>> {
+        print_meta2(&self.parse_meta2()?.code);
         Ok(())
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Op>, String> {
-        let meta2 = self.parse_meta2()?;
-        meta2_to_vm(meta2)
+    pub fn parse(&mut self) -> Result<Program, Vec<Diagnostic>> {
+        let mut errors = Vec::new();
+        let (meta2, pragmas) = self.parse_expansion(&mut errors);
+
+        // labels are resolved in whatever did expand, so their errors come with the others
+        let mut program = meta2_to_vm(meta2, pragmas, &mut errors);
+
+        if !errors.is_empty() {
+            return Err(self.limit_errors(errors));
+        }
+
+        self.removed = match self.optimize {
+            true => optimize::peephole(&mut program.code, &mut program.source_map),
+            false => 0,
+        };
+
+        Ok(program)
     }
-}
\ No newline at end of file
+
+    // all errors of the program, empty if it's correct
+    pub fn check(&mut self) -> Vec<Diagnostic> {
+        match self.parse() {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors,
+        }
+    }
+
+    pub fn sources(&self) -> &Sources {
+        &self.sources
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn codes(source: &str) -> Vec<ErrorCode> {
+        Parser::from_str(source).check().iter().map(|d| d.code()).collect()
+    }
+
+    #[test]
+    fn label_errors_come_with_the_others() {
+        let source = "\
+sbu %x
+jmp %a %b @nowhere
+@dup
+@dup
+";
+        assert_eq!(codes(source), vec![ErrorCode::MacroNotFound, ErrorCode::DuplicateLabel, ErrorCode::LabelNotFound]);
+        assert_eq!(codes("inc %x\n@end\njmp %x %x @end\n"), vec![]);
+    }
+
+    #[test]
+    fn missing_label_is_underlined() {
+        let errors = Parser::from_str("inc %x\njmp %x %x @nowhere\n").check();
+        let span = errors[0].span().unwrap();
+
+        assert_eq!((span.line(), span.sym(), span.len()), (2, 11, 8));
+    }
+
+    #[test]
+    fn parses_from_memory() {
+        let source = "macro twice X {\n    inc X\n    inc X\n}\n\ntwice %a\n@l\njmp %a %b @l\n";
+        let mut parser = Parser::from_str(source);
+        let program = parser.parse().unwrap_or_else(|_| panic!("doesn't parse"));
+
+        assert_eq!(program.code, vec![Op::Inc(0), Op::Inc(0), Op::Jmp(0, 1, 2)]);
+        assert_eq!((program.register("a"), program.register("b")), (Some(0), Some(1)));
+        assert_eq!(program.source_map.pos(2).map(|p| p.line()), Some(8));
+        assert_eq!(parser.sources().line(0, 6), Some("twice %a"));
+
+        // any reader gives the same program
+        let mut parser = Parser::new(Lexer::new(Cursor::new(source.as_bytes().to_vec())));
+        assert_eq!(parser.parse().ok().map(|p| p.code), Some(program.code));
+    }
+
+    #[test]
+    fn read_errors_are_reported() {
+        // not UTF-8
+        let mut parser = Parser::new(Lexer::new(Cursor::new(vec![b'i', b'n', b'c', b' ', b'%', 0xff, b'\n'])));
+        assert_eq!(parser.check().iter().map(|d| d.code()).collect::<Vec<_>>(), vec![ErrorCode::CannotReadFile]);
+    }
+
+    #[test]
+    fn locals_are_found_by_the_instruction() {
+        let source = "\
+macro inner X {
+    inc %.i
+    mov X %.i
+}
+
+macro outer X {
+    zer %.o
+    inner %.o
+    mov X %.o
+}
+
+outer %a
+inc %a
+";
+        let program = Parser::from_str(source).parse().unwrap_or_else(|_| panic!("doesn't parse"));
+        let (o, i) = (program.register_at(".o", 0).unwrap(), program.register_at(".i", 1).unwrap());
+
+        // `inc %.i` is in both expansions, the innermost first
+        let names: Vec<Vec<String>> = program.source_map.locals(1).iter().map(|l| l.iter().map(|(n, _)| n.clone()).collect()).collect();
+        assert_eq!(names, vec![vec![String::from("i")], vec![String::from("o")]]);
+        assert_eq!(program.register_at(".o", 1), Some(o));
+
+        assert_eq!(program.register_at(".i", 0), None);
+        assert_eq!(program.register_at(".o", 4), None);
+        assert_eq!(program.register_at("a", 4), program.register("a"));
+        assert_ne!(o, i);
+    }
+}
@@ -0,0 +1,93 @@
+use std::fs;
+
+use mur::bignat::BigNat;
+use mur::parser::Program;
+use serde_json::Value;
+
+// Initial register values given by `--input regs.json` and `--set name=value`.
+// A register is named as in the source without `%` or by raw index as `#3`.
+pub struct Presets {
+    values: Vec<(String, String)>
+}
+
+impl Presets {
+    pub fn new() -> Self {
+        Presets {
+            values: Vec::new()
+        }
+    }
+
+    pub fn add(&mut self, name: &str, value: &str) {
+        let name = name.strip_prefix('%').unwrap_or(name);
+        self.values.push((String::from(name), String::from(value)));
+    }
+
+    // JSON object of register names to numbers, big values may be written as decimal or `0x` hex strings
+    pub fn load(&mut self, path: &str) -> Result<(), String> {
+        let Ok(text) = fs::read_to_string(path) else {
+            return Err(format!("Cannot read input file '{}'", path));
+        };
+
+        let json: Value = match serde_json::from_str(&text) {
+            Ok(v) => v,
+            Err(err) => return Err(format!("Bad input file '{}': {}", path, err)),
+        };
+
+        let Value::Object(map) = json else {
+            return Err(format!("Bad input file '{}': expected an object of register values", path));
+        };
+
+        for (name, value) in map.iter() {
+            match value {
+                Value::Number(n) => self.add(name, &n.to_string()),
+                Value::String(s) => self.add(name, s),
+                _ => return Err(format!("Bad value of register '{}' in '{}'", name, path)),
+            }
+        }
+
+        Ok(())
+    }
+
+    // names which should be allocated before the program's own registers
+    pub fn names(&self) -> Vec<String> {
+        self.values.iter()
+            .filter(|(name, _)| !name.starts_with('#'))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    // Named presets are allocated first and move the other registers, so an index would point
+    // at another register depending on which names are given. The two can't be mixed.
+    pub fn registers(&self, program: &Program) -> Result<Vec<BigNat>, String> {
+        let by_index = self.values.iter().find(|(name, _)| name.starts_with('#'));
+        let by_name = self.values.iter().find(|(name, _)| !name.starts_with('#'));
+
+        if let (Some((index, _)), Some((name, _))) = (by_index, by_name) {
+            return Err(format!("Registers can't be given both by name and by index ('{}' and '{}')", name, index));
+        }
+
+        let mut regs = Vec::new();
+
+        for (name, value) in self.values.iter() {
+            let Some(r) = program.register(name) else {
+                return Err(format!("Bad register '{}'", name));
+            };
+
+            if !program.uses_register(r) {
+                return Err(format!("Register '{}' is not used by the program", name));
+            }
+
+            let value = match value.parse::<BigNat>() {
+                Ok(v) => v,
+                Err(err) => return Err(format!("Bad value of register '{}': {} ({})", name, value, err.str())),
+            };
+
+            if r >= regs.len() {
+                regs.resize(r + 1, BigNat::new());
+            }
+            regs[r] = value;
+        }
+
+        Ok(regs)
+    }
+}
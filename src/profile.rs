@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use crate::lexer::{FileId, LexPosType};
+use crate::meta2::FrameId;
+use crate::parser::Program;
+use crate::source::Sources;
+use crate::vm::{Env, Observer, Op, PosType};
+
+// rows shown in each table of the hot-spot report
+const HOT_SPOTS: usize = 20;
+
+// frame of the instructions written outside of macros
+const TOP_LEVEL: &str = "<top>";
+
+// Counts how many times every instruction was executed and every macro expansion was entered
+pub struct Profile {
+    counts: Vec<u64>,
+    frames: Vec<Option<FrameId>>, // expansion of every instruction
+    parents: Vec<Option<FrameId>>,
+    entries: Vec<u64>, // by frame, executions which came from outside of it
+    last: Option<FrameId> // expansion of the previous instruction
+}
+
+impl Observer for Profile {
+    #[inline(always)]
+    fn step(&mut self, ip: PosType, _: Op, _: PosType, _: &Env) {
+        self.counts[ip] += 1;
+
+        let frame = self.frames[ip];
+        if frame != self.last {
+            self.enter(frame);
+        }
+    }
+}
+
+impl Profile {
+    pub fn new(program: &Program) -> Self {
+        let frames = program.source_map.frames();
+        let parents = (0..frames.len()).map(|f| frames.get(f).parent()).collect();
+
+        Profile {
+            counts: vec![0; program.code.len()],
+            frames: (0..program.code.len()).map(|ip| program.source_map.origin(ip).and_then(|o| o.frame())).collect(),
+            parents: parents,
+            entries: vec![0; frames.len()],
+            last: None
+        }
+    }
+
+    fn chain(&self, frame: Option<FrameId>) -> Vec<FrameId> {
+        let mut chain = Vec::new();
+        let mut curr = frame;
+
+        while let Some(f) = curr {
+            chain.push(f);
+            curr = self.parents[f];
+        }
+
+        chain
+    }
+
+    // the expansions around `frame` which the previous instruction wasn't in are entered now
+    fn enter(&mut self, frame: Option<FrameId>) {
+        let from = self.chain(self.last);
+
+        for f in self.chain(frame) {
+            if from.contains(&f) {
+                break;
+            }
+
+            self.entries[f] += 1;
+        }
+
+        self.last = frame;
+    }
+
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    // by frame, how many times execution came into the expansion from outside of it
+    pub fn entries(&self) -> &[u64] {
+        &self.entries
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    // steps by the source line of instructions, the lines inside macro bodies sum all their expansions
+    pub fn lines(&self, program: &Program) -> HashMap<(FileId, LexPosType), u64> {
+        let mut lines = HashMap::new();
+
+        for (ip, count) in self.counts.iter().enumerate() {
+            if let Some(pos) = program.source_map.pos(ip) {
+                *lines.entry((pos.file(), pos.line())).or_insert(0) += count;
+            }
+        }
+
+        lines
+    }
+
+    // steps by macro name: the total of everything expanded from it and the self steps of its own instructions
+    pub fn macros(&self, program: &Program) -> HashMap<String, (u64, u64)> {
+        let mut macros: HashMap<String, (u64, u64)> = HashMap::new();
+
+        for (ip, count) in self.counts.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+
+            let stack = program.source_map.stack(ip);
+
+            // a recursive chain counts a macro only once
+            let mut seen = Vec::new();
+            for call in stack.iter() {
+                if !seen.contains(&&call.name) {
+                    seen.push(&call.name);
+                    macros.entry(call.name.clone()).or_default().0 += count;
+                }
+            }
+
+            if let Some(call) = stack.first() {
+                macros.entry(call.name.clone()).or_default().1 += count;
+            }
+        }
+
+        macros
+    }
+
+    // "folded stacks" for flamegraph tools, one `outer;inner count` line for every chain of macro calls
+    pub fn folded(&self, program: &Program) -> String {
+        let mut stacks: HashMap<String, u64> = HashMap::new();
+
+        for (ip, count) in self.counts.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+
+            let stack = program.source_map.stack(ip);
+            let names: Vec<&str> = stack.iter().rev().map(|call| call.name.as_str()).collect();
+            let chain = if names.is_empty() { String::from(TOP_LEVEL) } else { names.join(";") };
+
+            *stacks.entry(chain).or_insert(0) += count;
+        }
+
+        let mut stacks: Vec<_> = stacks.into_iter().collect();
+        stacks.sort();
+
+        stacks.iter().map(|(chain, count)| format!("{} {}\n", chain, count)).collect()
+    }
+
+    // hot-spot tables of lines and macros, sorted by steps
+    pub fn render(&self, program: &Program, sources: &Sources) -> String {
+        let total = self.total();
+        let percent = |n: u64| if total == 0 { 0.0 } else { n as f64 * 100.0 / total as f64 };
+
+        let mut out = format!("Profile: {} steps\n", total);
+
+        let mut lines: Vec<_> = self.lines(program).into_iter().filter(|(_, n)| *n > 0).collect();
+        lines.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let locations: Vec<String> = lines.iter().take(HOT_SPOTS).map(|((file, line), _)| format!("{}:{}", sources.name(*file), line)).collect();
+        let width = locations.iter().map(|l| l.len()).max().unwrap_or(0);
+
+        out += &format!("\n{:>12} {:>7}  line\n", "steps", "%");
+        for (((file, line), n), location) in lines.iter().zip(locations.iter()) {
+            let text = sources.line(*file, *line).unwrap_or("").trim();
+            out += &format!("{:>12} {:>6.2}%  {:<width$}  {}\n", n, percent(*n), location, text, width = width);
+        }
+
+        let mut macros: Vec<_> = self.macros(program).into_iter().collect();
+        macros.sort_by(|a, b| b.1.0.cmp(&a.1.0).then(b.1.1.cmp(&a.1.1)).then(a.0.cmp(&b.0)));
+
+        out += &format!("\n{:>12} {:>7} {:>12} {:>7}  macro\n", "total", "%", "self", "%");
+        for (name, (all, own)) in macros.iter().take(HOT_SPOTS) {
+            out += &format!("{:>12} {:>6.2}% {:>12} {:>6.2}%  {}\n", all, percent(*all), own, percent(*own), name);
+        }
+
+        out
+    }
+}
@@ -0,0 +1,266 @@
+use std::io::Write;
+
+use mur::bignat::BigNat;
+use mur::debug::{Debugger, Stop};
+use mur::lexer::LexPosType;
+use mur::parser::Program;
+use mur::source::Sources;
+use mur::vm::PosType;
+
+const HELP: &str = "\
+Commands:
+  break LINE | FILE:LINE | @LABEL   set a breakpoint, without arguments list them (b)
+  delete N                          delete a breakpoint (d)
+  step                              execute one instruction, entering macros (s)
+  next                              step over macro calls (n)
+  finish                            run until the current macro expansion ends (f)
+  continue                          run until a breakpoint or the end (c)
+  print NAME...                     show registers by name, `.x` for a macro local, `#3` for an index (p), `print/x` in hex
+  set NAME VALUE                    change a register, the value is decimal or `0x` hex
+  regs                              show all named registers and the locals of the current macro
+  list                              show the current line (l)
+  backtrace                         show the macro expansion stack (bt)
+  quit                              exit (q)
+An empty line repeats the last command.";
+
+// Interactive debugger reading commands from the program's input, `mur debug file.mur`
+pub struct Repl<'a> {
+    debugger: Debugger<'a>,
+    sources: &'a Sources,
+    breakpoints: Vec<(usize, String)>, // id and how the breakpoint was given
+    last: String
+}
+
+impl<'a> Repl<'a> {
+    pub fn new(debugger: Debugger<'a>, sources: &'a Sources) -> Self {
+        Repl {
+            debugger: debugger,
+            sources: sources,
+            breakpoints: Vec::new(),
+            last: String::new()
+        }
+    }
+
+    fn program(&self) -> &'a Program {
+        self.debugger.program()
+    }
+
+    pub fn run(&mut self) {
+        println!("Debugging, type `help` for the list of commands");
+        self.show_line();
+
+        loop {
+            print!("(mur) ");
+            let _ = std::io::stdout().flush();
+
+            // `inp` takes what comes after the command which ran it, the next command follows
+            let mut line = String::new();
+            match self.debugger.machine_mut().input_mut().read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => (),
+            }
+
+            let mut line = String::from(line.trim());
+            if line.is_empty() {
+                line = self.last.clone();
+            }
+
+            if !self.command(&line) {
+                break;
+            }
+
+            let _ = self.debugger.machine_mut().flush();
+            self.last = line;
+        }
+    }
+
+    // false when the debugger should exit
+    fn command(&mut self, line: &str) -> bool {
+        let mut words = line.split_whitespace();
+        let Some(cmd) = words.next() else {
+            return true;
+        };
+        let args: Vec<&str> = words.collect();
+
+        match cmd {
+            "b" | "break" => match args.first() {
+                Some(spec) => self.set_breakpoint(spec),
+                None => self.list_breakpoints(),
+            },
+            "d" | "delete" => match args.first().and_then(|a| a.parse().ok()) {
+                Some(id) if self.debugger.delete(id) => self.breakpoints.retain(|(i, _)| *i != id),
+                _ => println!("No such breakpoint"),
+            },
+            "s" | "step" => self.resume(|d| d.step()),
+            "n" | "next" => self.resume(|d| d.next()),
+            "f" | "finish" => self.resume(|d| d.finish()),
+            "c" | "continue" => self.resume(|d| d.cont()),
+            "p" | "print" | "p/x" | "print/x" => {
+                for name in args.iter() {
+                    self.print_reg(name, cmd.ends_with("/x"));
+                }
+            },
+            "set" => match args.as_slice() {
+                [name, value] => self.set_reg(name, value),
+                _ => println!("Expected `set NAME VALUE`"),
+            },
+            "regs" => {
+                let mut names: Vec<_> = self.program().registers.iter().collect();
+                names.sort_by_key(|(name, r)| (**r, name.as_str()));
+
+                for (name, r) in names.iter() {
+                    println!("%{} = {}", name, self.debugger.machine().reg(**r));
+                }
+
+                let locals = self.program().source_map.locals(self.debugger.machine().ip());
+                for (name, r) in locals.first().into_iter().flatten() {
+                    println!("%.{} = {}", name, self.debugger.machine().reg(*r));
+                }
+            },
+            "l" | "list" => self.show_line(),
+            "bt" | "backtrace" => self.backtrace(),
+            "h" | "help" => println!("{}", HELP),
+            "q" | "quit" => return false,
+            _ => println!("Unknown command '{}', type `help` for the list of commands", cmd),
+        }
+
+        true
+    }
+
+    fn set_breakpoint(&mut self, spec: &str) {
+        let id = if let Some(label) = spec.strip_prefix('@') {
+            self.debugger.break_at_label(label)
+        }
+        else {
+            let (file, line) = match spec.rsplit_once(':') {
+                Some((name, line)) => (self.sources.find_name(name), line),
+                None => (Some(0), spec), // the main file
+            };
+
+            let (Some(file), Ok(line)) = (file, line.parse::<LexPosType>()) else {
+                println!("Bad breakpoint '{}', expected LINE, FILE:LINE or @LABEL", spec);
+                return;
+            };
+
+            self.debugger.break_at_line(file, line)
+        };
+
+        match id {
+            Some(id) => {
+                println!("Breakpoint {} at {}", id, spec);
+                self.breakpoints.push((id, String::from(spec)));
+            },
+            None => println!("No code at {}", spec),
+        }
+    }
+
+    fn list_breakpoints(&self) {
+        if self.breakpoints.is_empty() {
+            println!("No breakpoints");
+        }
+
+        for (id, spec) in self.breakpoints.iter() {
+            println!("{}: {}", id, spec);
+        }
+    }
+
+    fn resume(&mut self, f: impl FnOnce(&mut Debugger<'a>) -> Stop) {
+        if let Some(halt) = self.debugger.machine().halt() {
+            println!("The program has stopped ({})", halt.str());
+            return;
+        }
+
+        let stop = f(&mut self.debugger);
+        let _ = self.debugger.machine_mut().flush();
+
+        match stop {
+            Stop::Step => self.show_line(),
+            Stop::Breakpoint(id) => {
+                println!("Breakpoint {}", id);
+                self.show_line();
+            },
+            Stop::Halt(halt) => println!("The program has stopped ({}) after {} steps", halt.str(), self.debugger.machine().steps()),
+        }
+    }
+
+    fn print_reg(&self, name: &str, hex: bool) {
+        let name = name.strip_prefix('%').unwrap_or(name);
+
+        let shown = if name.starts_with('#') { String::from(name) } else { format!("%{}", name) };
+
+        match self.program().register_at(name, self.debugger.machine().ip()) {
+            Some(r) if hex => println!("{} = {:#x}", shown, self.debugger.machine().reg(r)),
+            Some(r) => println!("{} = {}", shown, self.debugger.machine().reg(r)),
+            None => println!("No register '{}'", name),
+        }
+    }
+
+    fn set_reg(&mut self, name: &str, value: &str) {
+        let name = name.strip_prefix('%').unwrap_or(name);
+
+        let Some(r) = self.program().register_at(name, self.debugger.machine().ip()) else {
+            println!("No register '{}'", name);
+            return;
+        };
+
+        match value.parse::<BigNat>() {
+            Ok(v) => self.debugger.machine_mut().set_reg(r, v),
+            Err(err) => println!("Bad value '{}': {}", value, err.str()),
+        }
+    }
+
+    fn show_line(&self) {
+        let ip: PosType = self.debugger.machine().ip();
+
+        let Some(pos) = self.program().source_map.pos(ip) else {
+            println!("#{} end of the program", ip);
+            return;
+        };
+
+        println!("#{} {}", ip, self.sources.pos_str(pos));
+        if let Some(text) = self.sources.line(pos.file(), pos.line()) {
+            println!("{:>5} | {}", pos.line(), text);
+        }
+    }
+
+    fn backtrace(&self) {
+        let ip = self.debugger.machine().ip();
+        println!("#{} {}", ip, self.program().source_map.pos_str(ip, self.sources));
+
+        for call in self.program().source_map.stack(ip).iter() {
+            println!("    in expansion of `{}` at {}", call.name, self.sources.pos_str(call.span.pos()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use mur::parser::Parser;
+    use mur::vm::{Halt, Input, InputMode, Machine, Output, OutputMode};
+
+    use super::*;
+
+    #[test]
+    fn program_input_comes_between_commands() {
+        let mut parser = Parser::from_str("@loop\ninp %c\njmp %c %nl @done\njmp %c %c @loop\n@done\nout %c\n");
+        let program = parser.parse().unwrap_or_else(|_| panic!("doesn't parse"));
+
+        let mut regs = vec![BigNat::new(); 2];
+        regs[program.register("nl").unwrap()] = BigNat::from(10u64);
+
+        // the program reads the line after `continue`, the commands after it must still run
+        let script = "break @done\ncontinue\nab\nset c 65\ncontinue\n";
+        let input = Input::new(Cursor::new(script.as_bytes().to_vec()), InputMode::Byte);
+
+        let mut out = Vec::new();
+        let machine = Machine::new(&program.code, regs, input, Output::new(&mut out, OutputMode::Char));
+        let mut repl = Repl::new(Debugger::new(&program, machine), parser.sources());
+        repl.run();
+
+        assert_eq!(repl.debugger.machine().halt(), Some(Halt::End));
+        drop(repl);
+        assert_eq!(out, b"A");
+    }
+}
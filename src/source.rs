@@ -0,0 +1,73 @@
+use std::path::{Path, PathBuf};
+
+use crate::lexer::{FileId, LexPos, LexPosType};
+
+struct SourceFile {
+    name: String,
+    path: Option<PathBuf>,
+    lines: Vec<String>
+}
+
+pub struct Sources {
+    files: Vec<SourceFile>
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Sources {
+            files: Vec::new()
+        }
+    }
+
+    // path must be canonical, it is used to detect repeated and cyclic includes
+    pub fn add(&mut self, name: &str, path: Option<PathBuf>) -> FileId {
+        self.files.push(SourceFile {
+            name: String::from(name),
+            path: path,
+            lines: Vec::new()
+        });
+
+        return (self.files.len() - 1) as FileId;
+    }
+
+    pub fn name(&self, file: FileId) -> &str {
+        &self.files[file as usize].name
+    }
+
+    pub fn path(&self, file: FileId) -> Option<&Path> {
+        self.files[file as usize].path.as_deref()
+    }
+
+    pub fn set_lines(&mut self, file: FileId, lines: Vec<String>) {
+        self.files[file as usize].lines = lines;
+    }
+
+    // lines are numbered from 1, as in LexPos
+    pub fn line(&self, file: FileId, line: LexPosType) -> Option<&str> {
+        let i = (line as usize).checked_sub(1)?;
+        self.files.get(file as usize)?.lines.get(i).map(|l| l.as_str())
+    }
+
+    pub fn find(&self, path: &Path) -> Option<FileId> {
+        self.files.iter()
+            .position(|f| f.path.as_deref() == Some(path))
+            .map(|i| i as FileId)
+    }
+
+    // file by its name as shown in messages, or by the end of its path as `lib/arith.mur`
+    pub fn find_name(&self, name: &str) -> Option<FileId> {
+        self.files.iter()
+            .position(|f| f.name == name || f.path.as_deref().is_some_and(|p| p.ends_with(name)))
+            .map(|i| i as FileId)
+    }
+
+    pub fn pos_str(&self, pos: LexPos) -> String {
+        format!("{}:{}", self.name(pos.file()), pos.str())
+    }
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Sources::new()
+    }
+}
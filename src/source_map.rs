@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use crate::diagnostic::MacroCall;
+use crate::lexer::LexPos;
+use crate::meta2::{Frames, Origin};
+use crate::source::Sources;
+use crate::vm::{CellType, PosType};
+
+// Maps every instruction of the final code back to the source
+pub struct SourceMap {
+    origins: Vec<Origin>,
+    frames: Frames,
+    labels: HashMap<PosType, String> // instruction to the name of a label placed before it
+}
+
+impl SourceMap {
+    pub fn new(origins: Vec<Origin>, frames: Frames, labels: HashMap<PosType, String>) -> Self {
+        SourceMap {
+            origins: origins,
+            frames: frames,
+            labels: labels
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.origins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.origins.is_empty()
+    }
+
+    pub fn origin(&self, ip: PosType) -> Option<Origin> {
+        self.origins.get(ip).copied()
+    }
+
+    pub fn pos(&self, ip: PosType) -> Option<LexPos> {
+        self.origin(ip).map(|o| o.pos())
+    }
+
+    // macro invocations the instruction was expanded in, innermost first
+    pub fn stack(&self, ip: PosType) -> Vec<MacroCall> {
+        match self.origin(ip) {
+            Some(o) => self.frames.calls(o.frame()),
+            None => Vec::new(),
+        }
+    }
+
+    // local registers of the macro expansions the instruction is in, innermost first as in `stack`
+    pub fn locals(&self, ip: PosType) -> Vec<Vec<(String, CellType)>> {
+        let mut locals = Vec::new();
+        let mut curr = self.origin(ip).and_then(|o| o.frame());
+
+        while let Some(f) = curr {
+            let frame = self.frames.get(f);
+            let mut regs: Vec<_> = frame.locals().iter().map(|(name, r)| (name.to_string(), *r)).collect();
+            regs.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+
+            locals.push(regs);
+            curr = frame.parent();
+        }
+
+        locals
+    }
+
+    // `file:line:col` of the instruction
+    pub fn pos_str(&self, ip: PosType, sources: &Sources) -> String {
+        match self.pos(ip) {
+            Some(pos) => sources.pos_str(pos),
+            None => String::from("?:?"),
+        }
+    }
+
+    pub fn label(&self, ip: PosType) -> Option<&str> {
+        self.labels.get(&ip).map(|l| l.as_str())
+    }
+
+    pub fn frames(&self) -> &Frames {
+        &self.frames
+    }
+
+    // Follows the removal of instructions from the code, `new_ip` has the new place of every
+    // instruction and of the end, a removed instruction gets the place of the next kept one
+    pub fn remap(&mut self, keep: &[bool], new_ip: &[PosType]) {
+        self.origins = self.origins.iter()
+            .zip(keep.iter())
+            .filter_map(|(origin, keep)| keep.then_some(*origin))
+            .collect();
+
+        let mut labels = HashMap::new();
+        for (ip, name) in self.labels.drain() {
+            let ip = new_ip[ip];
+
+            // the same choice as for several labels before one instruction in the parser
+            match labels.get(&ip) {
+                Some(old) if *old <= name => (),
+                _ => { labels.insert(ip, name); },
+            }
+        }
+
+        self.labels = labels;
+    }
+}
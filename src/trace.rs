@@ -0,0 +1,95 @@
+use std::io::{BufWriter, Stderr, Write};
+
+use mur::parser::Program;
+use mur::source::Sources;
+use mur::vm::{CellType, Env, Observer, Op, PosType};
+
+#[derive(Clone, Default)]
+pub struct TraceOptions {
+    pub from: Option<PosType>,
+    pub to: Option<PosType>,       // exclusive
+    pub macro_name: Option<String> // only instructions expanded from this macro
+}
+
+impl TraceOptions {
+    // `A..B`, either end may be omitted
+    pub fn parse_range(&mut self, range: &str) -> Result<(), String> {
+        let bad_range = || format!("'--trace-range' expects 'from..to', got: {}", range);
+
+        let Some((from, to)) = range.split_once("..") else {
+            return Err(bad_range());
+        };
+
+        if !from.is_empty() {
+            self.from = Some(from.parse().map_err(|_| bad_range())?);
+        }
+
+        if !to.is_empty() {
+            self.to = Some(to.parse().map_err(|_| bad_range())?);
+        }
+
+        Ok(())
+    }
+}
+
+// Prints every executed instruction to stderr as
+// `#412 jmp %3(5) %7(5) -> @sub_out_14  [println.mur:95:9]`, registers are shown with their values after it
+pub struct Tracer<'a> {
+    program: &'a Program,
+    sources: &'a Sources,
+    traced: Vec<bool>, // filters are checked once for every instruction, not on every step
+    out: BufWriter<Stderr>
+}
+
+impl<'a> Tracer<'a> {
+    pub fn new(program: &'a Program, sources: &'a Sources, options: &TraceOptions) -> Self {
+        let traced = (0..program.code.len())
+            .map(|ip| {
+                options.from.is_none_or(|from| ip >= from)
+                    && options.to.is_none_or(|to| ip < to)
+                    && options.macro_name.as_ref().is_none_or(|name| program.source_map.stack(ip).iter().any(|call| call.name == *name))
+            })
+            .collect();
+
+        Tracer {
+            program: program,
+            sources: sources,
+            traced: traced,
+            out: BufWriter::new(std::io::stderr())
+        }
+    }
+
+    fn reg(env: &Env, r: CellType) -> String {
+        format!("%{}({})", r, env.reg(r))
+    }
+}
+
+impl Observer for Tracer<'_> {
+    fn step(&mut self, ip: PosType, op: Op, next_ip: PosType, env: &Env) {
+        if !self.traced[ip] {
+            return;
+        }
+
+        let text = match op {
+            Op::Zero(r) => format!("zer {}", Tracer::reg(env, r)),
+            Op::Inc(r) => format!("inc {}", Tracer::reg(env, r)),
+            Op::Mov(r1, r2) => format!("mov {} {}", Tracer::reg(env, r1), Tracer::reg(env, r2)),
+            Op::Jmp(r1, r2, to) => {
+                let mut text = format!("jmp {} {}", Tracer::reg(env, r1), Tracer::reg(env, r2));
+
+                if next_ip == to {
+                    match self.program.source_map.label(to) {
+                        Some(label) => text += &format!(" -> @{}", label),
+                        None => text += &format!(" -> #{}", to),
+                    }
+                }
+
+                text
+            },
+            Op::Out(r) => format!("out {}", Tracer::reg(env, r)),
+            Op::Inp(r) => format!("inp {}", Tracer::reg(env, r)),
+        };
+
+        let _ = writeln!(self.out, "#{} {}  [{}]", ip, text, self.program.source_map.pos_str(ip, self.sources));
+    }
+}
@@ -1,4 +1,9 @@
+use std::fmt;
+use std::io::{BufRead, BufWriter, Write};
+use std::time::{Duration, Instant};
+
 use crate::bignat::BigNat;
+use crate::optimize::{self, FusedCode, Loop};
 
 pub type CellType = usize;
 pub type PosType = usize;
@@ -10,22 +15,123 @@ pub enum Op {
     Mov(CellType, CellType),
     Jmp(CellType, CellType, PosType), // r1, r2, label
     Out(CellType),
+    Inp(CellType),
+}
+
+impl Op {
+    pub fn regs(&self) -> Vec<CellType> {
+        match *self {
+            Op::Zero(r)
+            | Op::Inc(r)
+            | Op::Out(r)
+            | Op::Inp(r) => vec![r],
+            Op::Mov(r1, r2)
+            | Op::Jmp(r1, r2, _) => vec![r1, r2],
+        }
+    }
+}
+
+// Value of a register. It's kept inline while it fits in a machine word and goes to the heap
+// only after an overflow. A `Big` value is never less than 2^64, so equal values are in the same variant.
+#[derive(Clone, PartialEq, Eq)]
+pub enum RegValue {
+    Small(u64),
+    Big(Box<BigNat>),
+}
+
+impl Default for RegValue {
+    fn default() -> Self {
+        RegValue::Small(0)
+    }
+}
+
+impl From<u128> for RegValue {
+    fn from(value: u128) -> Self {
+        match u64::try_from(value) {
+            Ok(v) => RegValue::Small(v),
+            Err(_) => RegValue::Big(Box::new(BigNat::from(value))),
+        }
+    }
 }
 
-struct Env {
-    regs: Vec<BigNat>,
-    zero: BigNat
+impl From<BigNat> for RegValue {
+    fn from(value: BigNat) -> Self {
+        match value.to_u64() {
+            Some(v) => RegValue::Small(v),
+            None => RegValue::Big(Box::new(value)),
+        }
+    }
+}
+
+impl RegValue {
+    #[inline(always)]
+    fn increment(&mut self) {
+        match self {
+            RegValue::Small(n) => match n.checked_add(1) {
+                Some(v) => *n = v,
+                None => *self = RegValue::Big(Box::new(BigNat::from(u64::MAX as u128 + 1))),
+            },
+            RegValue::Big(n) => n.increment(),
+        }
+    }
+
+    pub fn to_u64(&self) -> Option<u64> {
+        match self {
+            RegValue::Small(n) => Some(*n),
+            RegValue::Big(_) => None,
+        }
+    }
+
+    pub fn into_bignat(self) -> BigNat {
+        match self {
+            RegValue::Small(n) => BigNat::from(n),
+            RegValue::Big(n) => *n,
+        }
+    }
+}
+
+impl fmt::Display for RegValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegValue::Small(n) => fmt::Display::fmt(n, f),
+            RegValue::Big(n) => fmt::Display::fmt(n, f),
+        }
+    }
+}
+
+impl fmt::Debug for RegValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::LowerHex for RegValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegValue::Small(n) => fmt::LowerHex::fmt(n, f),
+            RegValue::Big(n) => fmt::LowerHex::fmt(&**n, f),
+        }
+    }
+}
+
+pub struct Env {
+    regs: Vec<RegValue>,
+    written: Vec<bool>,
+    touched: usize,
+    zero: RegValue
 }
 
 impl Env {
-    pub fn new() -> Self {
+    fn new(regs: Vec<BigNat>) -> Self {
         Env {
-            regs: Vec::new(),
-            zero: BigNat::new()
+            written: vec![false; regs.len()],
+            regs: regs.into_iter().map(RegValue::from).collect(),
+            touched: 0,
+            zero: RegValue::default()
         }
     }
 
-    pub fn reg(&self, r: CellType) -> &BigNat {
+    pub fn reg(&self, r: CellType) -> &RegValue {
         if r < self.regs.len() {
             &self.regs[r]
         }
@@ -34,29 +140,578 @@ impl Env {
         }
     }
 
-    pub fn reg_mut(&mut self, r: CellType) -> &mut BigNat {
+    fn reg_mut(&mut self, r: CellType) -> &mut RegValue {
         while r >= self.regs.len() {
-            self.regs.push(BigNat::new());
+            self.regs.push(RegValue::default());
+            self.written.push(false);
+        }
+
+        if !self.written[r] {
+            self.written[r] = true;
+            self.touched += 1;
         }
 
         return &mut self.regs[r];
     }
+
+    // the value if it fits in a word
+    pub(crate) fn small(&self, r: CellType) -> Option<u64> {
+        self.reg(r).to_u64()
+    }
+
+    pub(crate) fn set(&mut self, r: CellType, value: u128) {
+        *self.reg_mut(r) = RegValue::from(value);
+    }
+}
+
+// value read by `inp` in byte mode at the end of input, one past the last byte
+pub const EOF_BYTE: u64 = 256;
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum InputMode {
+    #[default]
+    Byte,   // next byte, EOF_BYTE at the end of input
+    Number, // next decimal number separated by whitespace, 0 at the end of input
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum OutputMode {
+    #[default]
+    Char,    // Unicode character with the value as code point
+    Byte,    // raw byte
+    Decimal, // decimal number and new line
+    Hex,     // hexadecimal number and new line
+}
+
+impl OutputMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "char" => Some(OutputMode::Char),
+            "byte" => Some(OutputMode::Byte),
+            "dec" => Some(OutputMode::Decimal),
+            "hex" => Some(OutputMode::Hex),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RuntimeError {
+    BadInput,
+    NotChar, // `out` value isn't a Unicode scalar value
+    NotByte, // `out` value is greater than 255
+    BadOutput,
+}
+
+pub struct Input {
+    reader: Box<dyn BufRead>,
+    mode: InputMode
+}
+
+impl Input {
+    pub fn new(reader: impl BufRead + 'static, mode: InputMode) -> Self {
+        Input {
+            reader: Box::new(reader),
+            mode: mode
+        }
+    }
+
+    pub fn stdin(mode: InputMode) -> Self {
+        Input::new(std::io::stdin().lock(), mode)
+    }
+
+    fn peek_byte(&mut self) -> Result<Option<u8>, RuntimeError> {
+        match self.reader.fill_buf() {
+            Ok(buf) => Ok(buf.first().copied()),
+            Err(_) => Err(RuntimeError::BadInput),
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<BigNat, RuntimeError> {
+        match self.peek_byte()? {
+            Some(b) => {
+                self.reader.consume(1);
+                Ok(BigNat::from(b as u64))
+            },
+            None => Ok(BigNat::from(EOF_BYTE)),
+        }
+    }
+
+    fn read_number(&mut self) -> Result<BigNat, RuntimeError> {
+        while let Some(b) = self.peek_byte()? {
+            if !b.is_ascii_whitespace() {
+                break;
+            }
+            self.reader.consume(1);
+        }
+
+        let mut digits = String::new();
+
+        while let Some(b) = self.peek_byte()? {
+            if b.is_ascii_whitespace() {
+                break;
+            }
+
+            if !b.is_ascii_digit() {
+                return Err(RuntimeError::BadInput);
+            }
+
+            digits.push(b as char);
+            self.reader.consume(1);
+        }
+
+        if digits.is_empty() {
+            return Ok(BigNat::new());
+        }
+
+        BigNat::from_str_radix(&digits, 10).map_err(|_| RuntimeError::BadInput)
+    }
+
+    pub fn read(&mut self) -> Result<BigNat, RuntimeError> {
+        match self.mode {
+            InputMode::Byte => self.read_byte(),
+            InputMode::Number => self.read_number(),
+        }
+    }
+
+    // a line which isn't for the program, e.g. a debugger command coming between its input
+    pub fn read_line(&mut self, line: &mut String) -> std::io::Result<usize> {
+        self.reader.read_line(line)
+    }
 }
 
-pub fn execute(code: Vec<Op>) {
-    let mut env = Env::new();
-    let mut ip: PosType = 0;
+// `out` writes to any sink, so the output can be captured in memory
+pub struct Output<'a> {
+    writer: Box<dyn Write + 'a>,
+    mode: OutputMode
+}
+
+impl<'a> Output<'a> {
+    pub fn new(writer: impl Write + 'a, mode: OutputMode) -> Self {
+        Output {
+            writer: Box::new(writer),
+            mode: mode
+        }
+    }
+
+    pub fn stdout(mode: OutputMode) -> Self {
+        Output::new(BufWriter::new(std::io::stdout()), mode)
+    }
+
+    pub fn write(&mut self, value: &RegValue) -> Result<(), RuntimeError> {
+        let res = match self.mode {
+            OutputMode::Char => {
+                let Some(c) = value.to_u64().and_then(|v| u32::try_from(v).ok()).and_then(char::from_u32) else {
+                    return Err(RuntimeError::NotChar);
+                };
+                write!(self.writer, "{}", c)
+            },
+            OutputMode::Byte => {
+                let Some(b) = value.to_u64().and_then(|v| u8::try_from(v).ok()) else {
+                    return Err(RuntimeError::NotByte);
+                };
+                self.writer.write_all(&[b])
+            },
+            OutputMode::Decimal => writeln!(self.writer, "{}", value),
+            OutputMode::Hex => writeln!(self.writer, "{:x}", value),
+        };
 
+        res.map_err(|_| RuntimeError::BadOutput)
+    }
+
+    pub fn flush(&mut self) -> Result<(), RuntimeError> {
+        self.writer.flush().map_err(|_| RuntimeError::BadOutput)
+    }
+}
+
+// the clock is checked once per this many steps, it's too slow to check on every one
+const TIME_CHECK_PERIOD: u64 = 4096;
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Halt {
+    End,
+    StepLimit,
+    Timeout,
+    Error(RuntimeError),
+}
+
+impl Halt {
+    pub fn str(&self) -> &'static str {
+        match self {
+            Halt::End => "end of the program",
+            Halt::StepLimit => "step limit exceeded",
+            Halt::Timeout => "timeout",
+            Halt::Error(RuntimeError::BadInput) => "bad input",
+            Halt::Error(RuntimeError::NotChar) => "value is not a character",
+            Halt::Error(RuntimeError::NotByte) => "value is not a byte",
+            Halt::Error(RuntimeError::BadOutput) => "cannot write output",
+        }
+    }
+}
+
+pub struct Report {
+    pub halt: Halt,
+    pub ip: PosType, // next instruction to execute, code length if fell off the end
+    pub steps: u64,
+    pub regs: Vec<BigNat>,
+    pub regs_touched: usize // number of registers written at least once
+}
 
-    while ip < code.len() {
-        match code[ip] {
-            Op::Zero(r) => env.reg_mut(r).zero(),
+impl Report {
+    pub fn reg(&self, r: CellType) -> BigNat {
+        self.regs.get(r).cloned().unwrap_or_default()
+    }
+}
+
+// Watches the execution, called after every executed instruction
+pub trait Observer {
+    // `next_ip` shows whether a jump was taken, `env` holds the registers after the instruction
+    fn step(&mut self, ip: PosType, op: Op, next_ip: PosType, env: &Env);
+}
+
+pub struct NoObserver;
+
+impl Observer for NoObserver {
+    #[inline(always)]
+    fn step(&mut self, _: PosType, _: Op, _: PosType, _: &Env) {}
+}
+
+impl<O: Observer> Observer for Option<O> {
+    #[inline(always)]
+    fn step(&mut self, ip: PosType, op: Op, next_ip: PosType, env: &Env) {
+        if let Some(o) = self {
+            o.step(ip, op, next_ip, env);
+        }
+    }
+}
+
+impl<A: Observer, B: Observer> Observer for (A, B) {
+    #[inline(always)]
+    fn step(&mut self, ip: PosType, op: Op, next_ip: PosType, env: &Env) {
+        self.0.step(ip, op, next_ip, env);
+        self.1.step(ip, op, next_ip, env);
+    }
+}
+
+// Executes the code one instruction at a time, so it can be stopped and resumed
+pub struct Machine<'a> {
+    code: &'a [Op],
+    loops: &'a [Option<Loop>], // of `FusedCode`, empty unless run by `execute_fused`
+    env: Env,
+    ip: PosType,
+    steps: u64,
+    input: Input,
+    output: Output<'a>,
+    halt: Option<Halt> // set when the program can't continue
+}
+
+impl<'a> Machine<'a> {
+    // `regs` are initial values of the registers, the missing ones start at zero
+    pub fn new(code: &'a [Op], regs: Vec<BigNat>, input: Input, output: Output<'a>) -> Self {
+        Machine {
+            code: code,
+            loops: &[],
+            env: Env::new(regs),
+            ip: 0,
+            steps: 0,
+            input: input,
+            output: output,
+            halt: None
+        }
+    }
+
+    // next instruction to execute, code length if fell off the end
+    pub fn ip(&self) -> PosType {
+        self.ip
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    pub fn reg(&self, r: CellType) -> &RegValue {
+        self.env.reg(r)
+    }
+
+    // changes a register between steps, e.g. from a debugger
+    pub fn set_reg(&mut self, r: CellType, value: BigNat) {
+        *self.env.reg_mut(r) = RegValue::from(value);
+    }
+
+    pub fn halt(&self) -> Option<Halt> {
+        self.halt
+    }
+
+    pub fn input_mut(&mut self) -> &mut Input {
+        &mut self.input
+    }
+
+    // the reason if the program can't continue, checks the end of code without a step
+    pub fn finished(&mut self) -> Option<Halt> {
+        if self.halt.is_none() && self.ip >= self.code.len() {
+            self.halt = Some(Halt::End);
+        }
+
+        self.halt
+    }
+
+    // executes the next instruction, returns the reason if the program can't continue
+    pub fn step(&mut self) -> Option<Halt> {
+        self.step_observed(&mut NoObserver)
+    }
+
+    pub fn step_observed<O: Observer>(&mut self, observer: &mut O) -> Option<Halt> {
+        self.step_within(observer, u64::MAX)
+    }
+
+    // A fused loop may take at most `budget` steps, otherwise only its first instruction
+    // is executed, so a step limit stops the program exactly where it would without it
+    fn step_within<O: Observer>(&mut self, observer: &mut O, budget: u64) -> Option<Halt> {
+        if self.halt.is_some() {
+            return self.halt;
+        }
+
+        if self.ip >= self.code.len() {
+            self.halt = Some(Halt::End);
+            return self.halt;
+        }
+
+        let ip = self.ip;
+        let env = &mut self.env;
+        let mut next_ip = ip + 1;
+        let op = self.code[ip];
+
+        // there are no loops with an observer, it has to see every step
+        if let Some(Some(l)) = self.loops.get(ip) {
+            if let Some((to, steps)) = optimize::run_loop(*l, self.code, ip, env, budget) {
+                self.ip = to;
+                self.steps += steps;
+                return None;
+            }
+        }
+
+        match op {
+            Op::Zero(r) => *env.reg_mut(r) = RegValue::Small(0),
             Op::Inc(r) => env.reg_mut(r).increment(),
             Op::Mov(r1, r2) => *env.reg_mut(r1) = env.reg(r2).clone(),
-            Op::Jmp(r1, r2, new_ip) => if env.reg(r1) == env.reg(r2) { ip = new_ip - 1; },
-            Op::Out(r) => env.reg(r).print(),
+            Op::Jmp(r1, r2, new_ip) => if env.reg(r1) == env.reg(r2) { next_ip = new_ip; },
+            Op::Out(r) => if let Err(err) = self.output.write(env.reg(r)) {
+                self.halt = Some(Halt::Error(err));
+                return self.halt;
+            },
+            // a prompt written before `inp` must be seen before the program waits for input
+            Op::Inp(r) => match self.output.flush().and_then(|_| self.input.read()) {
+                Ok(v) => *env.reg_mut(r) = RegValue::from(v),
+                Err(err) => {
+                    self.halt = Some(Halt::Error(err));
+                    return self.halt;
+                },
+            },
         }
 
-        ip += 1;
+        observer.step(ip, op, next_ip, &self.env);
+
+        self.ip = next_ip;
+        self.steps += 1;
+
+        None
     }
-}
\ No newline at end of file
+
+    // runs until the program ends or a limit is exceeded, the limits count from this call
+    pub fn run<O: Observer>(&mut self, limits: &Limits, observer: &mut O) -> Halt {
+        let deadline = limits.timeout.map(|t| Instant::now() + t);
+        let start = self.steps;
+        let mut next_check = 0;
+
+        let halt = loop {
+            let steps = self.steps - start;
+
+            // a program which has just ended isn't stopped by a limit
+            if self.ip < self.code.len() {
+                if limits.max_steps.is_some_and(|max| steps >= max) {
+                    break Halt::StepLimit;
+                }
+
+                // fused loops take many steps at once, so the check can't wait for an exact multiple
+                if steps >= next_check {
+                    if deadline.is_some_and(|d| Instant::now() >= d) {
+                        break Halt::Timeout;
+                    }
+                    next_check = steps + TIME_CHECK_PERIOD;
+                }
+            }
+
+            let budget = limits.max_steps.map_or(u64::MAX, |max| max - steps);
+
+            if let Some(halt) = self.step_within(observer, budget) {
+                break halt;
+            }
+        };
+
+        match self.flush() {
+            Err(err) if halt == Halt::End => Halt::Error(err),
+            _ => halt,
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(), RuntimeError> {
+        self.output.flush()
+    }
+
+    pub fn into_report(self, halt: Halt) -> Report {
+        Report {
+            halt: halt,
+            ip: self.ip,
+            steps: self.steps,
+            regs: self.env.regs.into_iter().map(RegValue::into_bignat).collect(),
+            regs_touched: self.env.touched
+        }
+    }
+}
+
+// `regs` are initial values of the registers, the missing ones start at zero
+pub fn execute(code: &[Op], regs: Vec<BigNat>, limits: &Limits, input: Input, output: Output) -> Report {
+    execute_observed(code, regs, limits, input, output, &mut NoObserver)
+}
+
+// Runs fused loops at once, the result is the same as of `execute` on the code as written
+pub fn execute_fused(fused: &FusedCode, regs: Vec<BigNat>, limits: &Limits, input: Input, output: Output) -> Report {
+    let mut machine = Machine::new(fused.code(), regs, input, output);
+    machine.loops = fused.loops();
+
+    let halt = machine.run(limits, &mut NoObserver);
+    machine.into_report(halt)
+}
+
+pub fn execute_observed<O: Observer>(code: &[Op], regs: Vec<BigNat>, limits: &Limits, input: Input, output: Output, observer: &mut O) -> Report {
+    let mut machine = Machine::new(code, regs, input, output);
+    let halt = machine.run(limits, observer);
+
+    machine.into_report(halt)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn run(code: &[Op], regs: &[u64], limits: &Limits) -> Report {
+        let input = Input::new(Cursor::new(Vec::new()), InputMode::Byte);
+        let output = Output::new(std::io::sink(), OutputMode::Decimal);
+
+        execute(code, regs.iter().map(|v| BigNat::from(*v)).collect(), limits, input, output)
+    }
+
+    // the report and everything written by `out`
+    fn run_io(code: &[Op], regs: &[u64], input: &str, in_mode: InputMode, out_mode: OutputMode) -> (Report, Vec<u8>) {
+        let mut out = Vec::new();
+        let input = Input::new(Cursor::new(input.as_bytes().to_vec()), in_mode);
+        let regs = regs.iter().map(|v| BigNat::from(*v)).collect();
+
+        let report = execute(code, regs, &Limits::default(), input, Output::new(&mut out, out_mode));
+        (report, out)
+    }
+
+    fn values(report: &Report) -> Vec<Option<u64>> {
+        report.regs.iter().map(|v| v.to_u64()).collect()
+    }
+
+    #[test]
+    fn report_of_a_run() {
+        // %0 += %1 by counting %2 up to it
+        let code = [Op::Jmp(2, 1, 4), Op::Inc(0), Op::Inc(2), Op::Jmp(3, 3, 0)];
+        let report = run(&code, &[5, 3], &Limits::default());
+
+        assert_eq!(report.halt, Halt::End);
+        assert_eq!((report.ip, report.steps), (4, 13));
+        assert_eq!(values(&report), vec![Some(8), Some(3), Some(3)]);
+        assert_eq!(report.regs_touched, 2);
+
+        let report = run(&code, &[5, 3], &Limits { max_steps: Some(6), ..Limits::default() });
+        assert_eq!((report.halt, report.ip, report.steps), (Halt::StepLimit, 2, 6));
+        assert_eq!(values(&report), vec![Some(7), Some(3), Some(1)]);
+
+        // a register past the end of the initial ones reads as zero
+        let report = run(&[Op::Mov(0, 5)], &[9], &Limits::default());
+        assert_eq!((report.reg(0).to_u64(), report.reg(5).to_u64()), (Some(0), Some(0)));
+
+        let report = run(&[Op::Inc(0)], &[u64::MAX], &Limits::default());
+        assert_eq!(report.reg(0), BigNat::from(u64::MAX as u128 + 1));
+    }
+
+    #[test]
+    fn inp_reads_bytes_and_numbers() {
+        let code = [Op::Inp(0), Op::Inp(1), Op::Inp(2)];
+
+        let (report, _) = run_io(&code, &[], "é", InputMode::Byte, OutputMode::Char);
+        assert_eq!(values(&report), vec![Some(0xc3), Some(0xa9), Some(EOF_BYTE)]);
+
+        let (report, _) = run_io(&code, &[], " 12\n\t340282366920938463463374607431768211456 ", InputMode::Number, OutputMode::Char);
+        assert_eq!(report.halt, Halt::End);
+        assert_eq!(report.regs[1], &BigNat::from(1u128 << 64) * &BigNat::from(1u128 << 64));
+        assert_eq!((report.reg(0).to_u64(), report.reg(2).to_u64()), (Some(12), Some(0)));
+
+        // the end of input is read again and again
+        let (report, _) = run_io(&code, &[7, 7, 7], "", InputMode::Number, OutputMode::Char);
+        assert_eq!(values(&report), vec![Some(0), Some(0), Some(0)]);
+
+        let (report, _) = run_io(&code, &[], "5 12x", InputMode::Number, OutputMode::Char);
+        assert_eq!((report.halt, report.ip), (Halt::Error(RuntimeError::BadInput), 1));
+        assert_eq!(report.reg(0).to_u64(), Some(5));
+    }
+
+    #[test]
+    fn out_modes_and_their_errors() {
+        let code = [Op::Out(0), Op::Out(1)];
+        let out = |regs: &[u64], mode| run_io(&code, regs, "", InputMode::Byte, mode);
+
+        assert_eq!(out(&[65, 0x1f600], OutputMode::Char).1, "A😀".as_bytes());
+        assert_eq!(out(&[0, 255], OutputMode::Byte).1, vec![0, 255]);
+        assert_eq!(out(&[10, u64::MAX], OutputMode::Decimal).1, b"10\n18446744073709551615\n");
+        assert_eq!(out(&[10, u64::MAX], OutputMode::Hex).1, b"a\nffffffffffffffff\n");
+
+        // surrogates and values past the last code point aren't characters
+        for bad in [0xd800, 0xdfff, 0x110000, u64::MAX] {
+            let (report, written) = out(&[65, bad], OutputMode::Char);
+            assert_eq!((report.halt, report.ip, written), (Halt::Error(RuntimeError::NotChar), 1, b"A".to_vec()));
+        }
+
+        let (report, written) = out(&[1, 256], OutputMode::Byte);
+        assert_eq!((report.halt, report.ip, written), (Halt::Error(RuntimeError::NotByte), 1, vec![1]));
+
+        // a value which doesn't fit in a word is a number only
+        let big = [Op::Inc(0), Op::Out(0)];
+        for (mode, text) in [(OutputMode::Decimal, "18446744073709551616\n"), (OutputMode::Hex, "10000000000000000\n")] {
+            assert_eq!(run_io(&big, &[u64::MAX], "", InputMode::Byte, mode).1, text.as_bytes());
+        }
+        assert_eq!(run_io(&big, &[u64::MAX], "", InputMode::Byte, OutputMode::Char).0.halt, Halt::Error(RuntimeError::NotChar));
+        assert_eq!(run_io(&big, &[u64::MAX], "", InputMode::Byte, OutputMode::Byte).0.halt, Halt::Error(RuntimeError::NotByte));
+    }
+
+    #[test]
+    fn output_is_captured_in_memory() {
+        // copies input to output until `inp` gives EOF_BYTE, kept in %1
+        let code = [Op::Inp(0), Op::Jmp(0, 1, 4), Op::Out(0), Op::Jmp(2, 2, 0)];
+        let regs = vec![BigNat::new(), BigNat::from(EOF_BYTE)];
+
+        // the buffer is written out when the program ends
+        let mut out = Vec::new();
+        let input = Input::new(Cursor::new("héllo\n".as_bytes().to_vec()), InputMode::Byte);
+        let report = execute(&code, regs, &Limits::default(), input, Output::new(BufWriter::new(&mut out), OutputMode::Byte));
+
+        assert_eq!(out, "héllo\n".as_bytes());
+        assert_eq!((report.halt, report.ip, report.steps), (Halt::End, 4, 4 * 7 + 2));
+        assert_eq!(values(&report), vec![Some(EOF_BYTE), Some(EOF_BYTE)]);
+    }
+}